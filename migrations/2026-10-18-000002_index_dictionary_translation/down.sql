@@ -0,0 +1 @@
+DROP INDEX dictionary_translation;
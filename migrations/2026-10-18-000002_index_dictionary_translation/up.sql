@@ -0,0 +1,2 @@
+-- Reverse lookups of a whole translation, for suggestions and drills.
+CREATE INDEX dictionary_translation ON dictionary(translation);
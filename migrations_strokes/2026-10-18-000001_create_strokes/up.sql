@@ -0,0 +1,13 @@
+-- Every stroke written, for statistics and drills.
+CREATE TABLE strokes (
+    id INTEGER PRIMARY KEY AUTOINCREMENT NOT NULL,
+    session TEXT NOT NULL,
+    -- milliseconds since the epoch
+    timestamp BIGINT NOT NULL,
+    -- the steno keys pressed, space separated
+    raw TEXT NOT NULL,
+    chord TEXT NOT NULL,
+    translation TEXT,
+    undone BOOLEAN NOT NULL DEFAULT 0
+);
+CREATE INDEX strokes_session ON strokes(session);
@@ -0,0 +1,107 @@
+use std::fmt;
+use std::iter::Peekable;
+use std::str::Chars;
+
+/// A key combo from a `{#...}` translation: keys pressed in turn, a key
+/// followed by parentheses held down around the combo inside them.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Combo {
+    Press(String),
+    Hold(String, Vec<Combo>),
+}
+impl Combo {
+    /// Parse Plover's key combo syntax, `Control_L(c)`, `Return` or
+    /// `Alt_L(Shift_L(Tab)) space`.
+    pub fn parse(combo: &str) -> Result<Vec<Combo>, String> {
+        Self::sequence(&mut combo.chars().peekable(), 0)
+    }
+
+    fn sequence(chars: &mut Peekable<Chars<'_>>, depth: usize) -> Result<Vec<Combo>, String> {
+        let mut combos = Vec::new();
+        let mut name = String::new();
+        let flush = |name: &mut String, combos: &mut Vec<Combo>| {
+            if !name.is_empty() {
+                combos.push(Combo::Press(std::mem::take(name)));
+            }
+        };
+        loop {
+            match chars.next() {
+                Some('(') => {
+                    if name.is_empty() {
+                        return Err("( without a key to hold".to_string());
+                    }
+                    let inner = Self::sequence(chars, depth + 1)?;
+                    combos.push(Combo::Hold(std::mem::take(&mut name), inner));
+                }
+                Some(')') => {
+                    if depth == 0 {
+                        return Err("unmatched )".to_string());
+                    }
+                    flush(&mut name, &mut combos);
+                    return Ok(combos);
+                }
+                Some(c) if c.is_whitespace() => flush(&mut name, &mut combos),
+                Some(c) => name.push(c),
+                None => {
+                    if depth > 0 {
+                        return Err("unclosed (".to_string());
+                    }
+                    flush(&mut name, &mut combos);
+                    return Ok(combos);
+                }
+            }
+        }
+    }
+}
+impl fmt::Display for Combo {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Combo::Press(name) => write!(f, "{}", name),
+            Combo::Hold(name, inner) => {
+                let inner: Vec<String> = inner.iter().map(Combo::to_string).collect();
+                write!(f, "{}({})", name, inner.join(" "))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use test_case::test_case;
+
+    fn press(name: &str) -> Combo {
+        Combo::Press(name.to_string())
+    }
+
+    fn hold(name: &str, inner: Vec<Combo>) -> Combo {
+        Combo::Hold(name.to_string(), inner)
+    }
+
+    #[test_case("Return", vec![press("Return")] ; "single key")]
+    #[test_case("Control_L(c)", vec![hold("Control_L", vec![press("c")])] ; "held key")]
+    #[test_case("Alt_L(Shift_L(Tab)) space", vec![hold("Alt_L", vec![hold("Shift_L", vec![press("Tab")])]), press("space")] ; "nested")]
+    #[test_case("  a   b ", vec![press("a"), press("b")] ; "extra spaces")]
+    #[test_case("Control_L(a b)", vec![hold("Control_L", vec![press("a"), press("b")])] ; "held around several")]
+    #[test_case("Shift_L()", vec![hold("Shift_L", vec![])] ; "held around nothing")]
+    #[test_case("", vec![] ; "empty")]
+    fn parse(combo: &str, combos: Vec<Combo>) {
+        assert_eq!(Combo::parse(combo), Ok(combos));
+    }
+
+    #[test_case("(a)", "( without a key to hold" ; "hold without key")]
+    #[test_case("a)", "unmatched )" ; "unmatched")]
+    #[test_case("Control_L(a", "unclosed (" ; "unclosed")]
+    fn parse_error(combo: &str, error: &str) {
+        assert_eq!(Combo::parse(combo), Err(error.to_string()));
+    }
+
+    #[test_case("Return" ; "single key")]
+    #[test_case("Alt_L(Shift_L(Tab))" ; "nested")]
+    #[test_case("Control_L(a b)" ; "held around several")]
+    fn display(combo: &str) {
+        let combos = Combo::parse(combo).unwrap();
+        assert_eq!(combos.len(), 1);
+        assert_eq!(combos[0].to_string(), combo);
+    }
+}
@@ -0,0 +1,57 @@
+use crate::engine::Engine;
+use crate::workers::control::{self, SocketControl, SocketStatus};
+use crate::workers::{serial::DeviceStatus, Worker, WorkerPool};
+use crate::{info, warn};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread;
+use std::time::Duration;
+
+static STOP: AtomicBool = AtomicBool::new(false);
+
+extern "C" fn on_signal(_: libc::c_int) {
+    STOP.store(true, Ordering::SeqCst);
+}
+
+/// Run without the TUI until SIGTERM or SIGINT, logging what is translated.
+pub fn run(worker_pool: WorkerPool, mut engine: Engine, tick_rate: Duration) {
+    let handler = on_signal as extern "C" fn(libc::c_int) as libc::sighandler_t;
+    unsafe {
+        libc::signal(libc::SIGTERM, handler);
+        libc::signal(libc::SIGINT, handler);
+    }
+    info!("Running headless");
+    while !STOP.load(Ordering::SeqCst) {
+        let keyboard = worker_pool.keyboard.as_ref().and_then(|k| k.recv());
+        let statuses: Vec<DeviceStatus> = worker_pool.serial.recv().into_iter().chain(keyboard).collect();
+        let requests: Vec<SocketStatus> = worker_pool.control.iter().flat_map(|c| c.recv()).collect();
+        if statuses.is_empty() && requests.is_empty() {
+            thread::sleep(tick_rate);
+        }
+        for SocketStatus::Request(client, command) in requests {
+            let reply = engine.control(&command);
+            if let Some(control) = &worker_pool.control {
+                control.send(SocketControl::Reply(client, reply));
+            }
+        }
+        for status in statuses {
+            match status {
+                DeviceStatus::Input(chord) => {
+                    let command = engine.translate(&chord);
+                    info!("{} => {:?}", chord.steno(), command);
+                    if let Some(control) = &worker_pool.control {
+                        control.send(SocketControl::Publish(control::stroke(&chord)));
+                        if let Some(command) = &command {
+                            control.send(SocketControl::Publish(control::translation(&chord, command)));
+                        }
+                    }
+                }
+                DeviceStatus::Malformed(bytes) => warn!("Dropped malformed frame {:02x?}", bytes),
+                // the workers log their own connection changes
+                DeviceStatus::Connection(_) => {}
+            }
+        }
+    }
+    info!("Shutting down");
+    engine.finish();
+    worker_pool.join();
+}
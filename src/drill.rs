@@ -0,0 +1,255 @@
+use crate::steno::{ActionSymbol, Command, Dictionary};
+use crate::{info, warn};
+use json::{object, JsonValue};
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use std::time::{Duration, Instant};
+
+/// How a word of the lesson went.
+#[derive(Clone, Debug)]
+pub struct Attempt {
+    pub word: String,
+    /// Written without an undo or a wrong word first.
+    pub clean: bool,
+    /// From the word being shown to it being written.
+    pub latency: Duration,
+    pub strokes: usize,
+}
+
+/// A lesson: words to write one after the other, each shown with the
+/// shortest outline the dictionaries have for it.
+pub struct Drill {
+    pub lesson: String,
+    /// Where `finish` appends the results as a line of JSON.
+    pub export: Option<String>,
+    words: Vec<(String, Option<String>)>,
+    current: usize,
+    /// What has been written towards the current word.
+    written: String,
+    shown: Instant,
+    strokes: usize,
+    clean: bool,
+    pub attempts: Vec<Attempt>,
+}
+impl Drill {
+    /// Read a lesson, a word or phrase per line. Blank lines and lines
+    /// starting with `#` are skipped.
+    pub fn from_file(path: &str, dictionary: &Dictionary) -> Result<Self, String> {
+        let contents = fs::read_to_string(path).map_err(|e| format!("{}: {}", path, e))?;
+        let words: Vec<(String, Option<String>)> = contents
+            .lines()
+            .map(str::trim)
+            .filter(|line| !line.is_empty() && !line.starts_with('#'))
+            .map(|word| {
+                let outline = dictionary.outlines(word).into_iter().next().map(|e| e.chord);
+                (word.to_string(), outline)
+            })
+            .collect();
+        if words.is_empty() {
+            return Err(format!("{}: no words", path));
+        }
+        info!("Drill {}: {} words", path, words.len());
+        Ok(Self::new(path, words))
+    }
+
+    fn new(lesson: &str, words: Vec<(String, Option<String>)>) -> Self {
+        Self {
+            lesson: lesson.to_string(),
+            export: None,
+            words,
+            current: 0,
+            written: String::new(),
+            shown: Instant::now(),
+            strokes: 0,
+            clean: true,
+            attempts: Vec::new(),
+        }
+    }
+
+    /// The word to write next and its outline, nothing once the lesson is done.
+    pub fn target(&self) -> Option<(&str, Option<&str>)> {
+        self.words
+            .get(self.current)
+            .map(|(word, outline)| (word.as_str(), outline.as_deref()))
+    }
+
+    pub fn written(&self) -> &str {
+        self.written.trim_start()
+    }
+
+    pub fn progress(&self) -> (usize, usize) {
+        (self.current, self.words.len())
+    }
+
+    /// Follow what a stroke wrote, moving on once the target is written.
+    /// Case is ignored, the formatter capitalises sentence starts. A wrong
+    /// word is dropped when the next one starts, so the target can be written
+    /// again without undoing it.
+    pub fn record(&mut self, command: &Command, erase: &ActionSymbol, typed: &str) {
+        let word = match self.words.get(self.current) {
+            Some((word, _)) => word.to_lowercase(),
+            None => return,
+        };
+        if let Command::Undo(_) = command {
+            self.clean = false;
+        }
+        let keep = self.written.chars().count().saturating_sub(erase.erased());
+        self.written = self.written.chars().take(keep).collect();
+        if typed.starts_with(' ') && !word.starts_with(&self.written().to_lowercase()) {
+            self.written.clear();
+            self.clean = false;
+        }
+        self.written.push_str(typed);
+        self.strokes += 1;
+        let written = self.written().to_lowercase();
+        if written == word {
+            let attempt = Attempt {
+                word: self.words[self.current].0.clone(),
+                clean: self.clean,
+                latency: self.shown.elapsed(),
+                strokes: self.strokes,
+            };
+            info!("Drill {:?}", attempt);
+            self.attempts.push(attempt);
+            self.current += 1;
+            self.written.clear();
+            self.shown = Instant::now();
+            self.strokes = 0;
+            self.clean = true;
+        }
+    }
+
+    /// Words written cleanly, as a percentage of those written.
+    pub fn accuracy(&self) -> f64 {
+        if self.attempts.is_empty() {
+            return 0.0;
+        }
+        let clean = self.attempts.iter().filter(|a| a.clean).count();
+        clean as f64 * 100.0 / self.attempts.len() as f64
+    }
+
+    pub fn mean_latency(&self) -> Duration {
+        match self.attempts.len() {
+            0 => Duration::default(),
+            n => self.attempts.iter().map(|a| a.latency).sum::<Duration>() / n as u32,
+        }
+    }
+
+    pub fn to_json(&self) -> JsonValue {
+        let attempts: Vec<JsonValue> = self
+            .attempts
+            .iter()
+            .map(|a| {
+                object! {
+                    "word" => a.word.as_str(),
+                    "clean" => a.clean,
+                    "latency_ms" => a.latency.as_millis() as u64,
+                    "strokes" => a.strokes,
+                }
+            })
+            .collect();
+        object! {
+            "lesson" => self.lesson.as_str(),
+            "words" => self.words.len(),
+            "accuracy" => self.accuracy(),
+            "mean_latency_ms" => self.mean_latency().as_millis() as u64,
+            "attempts" => attempts,
+        }
+    }
+
+    /// Log the results and append them to `export` when set.
+    pub fn finish(&self, session: &str) {
+        let mut results = self.to_json();
+        results["session"] = session.into();
+        info!("Drill results {}", results.dump());
+        let path = match &self.export {
+            Some(path) => path,
+            None => return,
+        };
+        let written = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .and_then(|mut file| writeln!(file, "{}", results.dump()));
+        if let Err(e) = written {
+            warn!("{}: {}", path, e);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::format::{Emit, Formatter};
+
+    /// A drill fed by the formatter, as the engine feeds it.
+    struct Writer {
+        drill: Drill,
+        formatter: Formatter,
+    }
+    impl Writer {
+        fn new(words: &[&str]) -> Self {
+            Self {
+                drill: Drill::new("test", words.iter().map(|w| (w.to_string(), None)).collect()),
+                formatter: Formatter::new(),
+            }
+        }
+
+        fn stroke(&mut self, command: Command) {
+            let (erase, emits) = self.formatter.apply(&command);
+            self.drill.record(&command, &erase, &Emit::text(&emits));
+        }
+
+        fn write(&mut self, translation: &str) {
+            self.stroke(Command::Output(translation.to_string()));
+        }
+    }
+
+    #[test]
+    fn suffix_erasing_a_letter() {
+        let mut writer = Writer::new(&["carries", "on"]);
+        writer.write("carry");
+        writer.write("{^s}");
+        let drill = writer.drill;
+        assert_eq!(drill.progress(), (1, 2));
+        assert!(drill.attempts[0].clean);
+        assert_eq!(drill.attempts[0].strokes, 2);
+    }
+
+    #[test]
+    fn case_is_ignored() {
+        let mut writer = Writer::new(&["Cat", "dog"]);
+        writer.write("cat");
+        writer.write("Dog");
+        let drill = writer.drill;
+        assert_eq!(drill.progress(), (2, 2));
+        assert!(drill.attempts.iter().all(|a| a.clean));
+        assert_eq!(drill.target(), None);
+    }
+
+    #[test]
+    fn wrong_stroke_then_undo() {
+        let mut writer = Writer::new(&["cat"]);
+        writer.write("cap");
+        assert_eq!(writer.drill.written(), "cap");
+        writer.stroke(Command::Undo(Vec::new()));
+        assert_eq!(writer.drill.written(), "");
+        writer.write("cat");
+        let drill = writer.drill;
+        assert_eq!(drill.attempts.len(), 1);
+        assert!(!drill.attempts[0].clean);
+        assert_eq!(drill.attempts[0].strokes, 3);
+    }
+
+    #[test]
+    fn wrong_word_is_dropped_at_the_next_word() {
+        let mut writer = Writer::new(&["the cat"]);
+        for translation in &["the", "cap", "the", "cat"] {
+            writer.write(translation);
+        }
+        let drill = writer.drill;
+        assert_eq!(drill.attempts.len(), 1);
+        assert!(!drill.attempts[0].clean);
+        assert_eq!(drill.accuracy(), 0.0);
+    }
+}
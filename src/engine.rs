@@ -0,0 +1,149 @@
+use crate::format::Formatter;
+use crate::import::Conflict;
+use crate::models::NewEntry;
+use crate::drill::Drill;
+use crate::format::Emit;
+use crate::output::Output;
+use crate::stats::Stats;
+use crate::steno::{Chord, Command, Dictionary};
+use crate::strokelog::StrokeLog;
+use diesel::QueryResult;
+use json::{object, JsonValue};
+use log::info;
+
+/// Turns chords into output: looks them up in the dictionary stack, formats
+/// the translation and sends it to the output backend.
+pub struct Engine {
+    pub dictionary: Dictionary,
+    /// Whether strokes are translated at all.
+    pub enabled: bool,
+    formatter: Formatter,
+    backend: Box<dyn Output>,
+    /// Tags this run's strokes in the stroke log.
+    pub session: String,
+    pub stats: Stats,
+    /// A lesson being drilled, fed what each stroke writes.
+    pub drill: Option<Drill>,
+    pub log: Option<StrokeLog>,
+}
+impl Engine {
+    pub fn new(dictionary: Dictionary, backend: Box<dyn Output>) -> Self {
+        Self {
+            dictionary,
+            enabled: true,
+            formatter: Formatter::new(),
+            backend,
+            session: chrono::Local::now().format("%Y-%m-%dT%H:%M:%S").to_string(),
+            stats: Stats::new(),
+            drill: None,
+            log: None,
+        }
+    }
+
+    /// Translate a chord and send the result, returning the command it
+    /// resolved to, or nothing while output is off.
+    pub fn translate(&mut self, chord: &Chord) -> Option<Command> {
+        if !self.enabled {
+            return None;
+        }
+        let command = chord.resolve(&mut self.dictionary);
+        let (sym, emits) = self.formatter.apply(&command);
+        let typed = Emit::text(&emits);
+        self.stats.record(&command, &sym, typed.chars().count());
+        if let Some(drill) = &mut self.drill {
+            drill.record(&command, &sym, &typed);
+        }
+        self.backend.send(sym, emits);
+        if let Some(log) = &mut self.log {
+            log.record(chord, &command);
+        }
+        Some(command)
+    }
+
+    /// Wrap up the session's statistics, drill and stroke log.
+    pub fn finish(&mut self) {
+        if let Some(log) = &mut self.log {
+            log.flush();
+        }
+        self.stats.finish(&self.session);
+        if let Some(drill) = &self.drill {
+            drill.finish(&self.session);
+        }
+    }
+
+    /// Write an entry to the top dictionary, replacing any it had for
+    /// `strokes`. Lookups go to the database so it applies from the next
+    /// stroke on.
+    pub fn add(&self, strokes: &str, translation: &str) -> QueryResult<()> {
+        let entry = NewEntry {
+            chord: strokes.to_string(),
+            translation: translation.to_string(),
+        };
+        self.dictionary.top().insert(&[entry], Conflict::Replace)?;
+        info!("Added {} => {} to {}", strokes, translation, self.dictionary.top().name);
+        Ok(())
+    }
+
+    fn dictionaries(&self) -> JsonValue {
+        let sources: Vec<JsonValue> = self
+            .dictionary
+            .sources()
+            .iter()
+            .map(|s| object! { "name" => s.name.as_str(), "enabled" => s.enabled })
+            .collect();
+        JsonValue::from(sources)
+    }
+
+    /// Answer a control socket command:
+    ///
+    /// - `{"command": "toggle_output"}`, or with `"enabled"` to set it
+    /// - `{"command": "dictionaries"}`
+    /// - `{"command": "toggle_dictionary", "name": "user"}`, or by `"index"`
+    /// - `{"command": "last"}`, the last translation
+    /// - `{"command": "stats"}`, this session's statistics
+    /// - `{"command": "add", "strokes": "KAT", "translation": "cat"}`, into
+    ///   the top dictionary
+    pub fn control(&mut self, command: &JsonValue) -> JsonValue {
+        let error = |e: &str| object! { "ok" => false, "error" => e };
+        match command["command"].as_str() {
+            Some("toggle_output") => {
+                self.enabled = command["enabled"].as_bool().unwrap_or(!self.enabled);
+                info!("Output enabled: {}", self.enabled);
+                object! { "ok" => true, "enabled" => self.enabled }
+            }
+            Some("dictionaries") => object! { "ok" => true, "dictionaries" => self.dictionaries() },
+            Some("toggle_dictionary") => {
+                let index = match (command["index"].as_usize(), command["name"].as_str()) {
+                    (Some(index), _) => Some(index),
+                    (None, Some(name)) => self.dictionary.sources().iter().position(|s| s.name == name),
+                    (None, None) => None,
+                };
+                match index.filter(|&i| i < self.dictionary.sources().len()) {
+                    Some(i) => {
+                        self.dictionary.toggle(i);
+                        object! { "ok" => true, "dictionaries" => self.dictionaries() }
+                    }
+                    None => error("no such dictionary"),
+                }
+            }
+            Some("last") => match self.dictionary.last() {
+                Some(last) => object! {
+                    "ok" => true,
+                    "strokes" => last.outline(),
+                    "translation" => last.text.clone(),
+                },
+                None => object! { "ok" => true, "strokes" => JsonValue::Null, "translation" => JsonValue::Null },
+            },
+            Some("stats") => object! { "ok" => true, "session" => self.session.as_str(), "stats" => self.stats.to_json() },
+            Some("add") => match (command["strokes"].as_str(), command["translation"].as_str()) {
+                (Some(strokes), Some(translation)) if !strokes.is_empty() => match self.add(strokes, translation) {
+                    Ok(()) => object! { "ok" => true },
+                    Err(e) => error(&e.to_string()),
+                },
+                _ => error("add needs strokes and translation"),
+            },
+            Some(other) => error(&format!("unknown command {}", other)),
+            None => error("missing command"),
+        }
+    }
+}
@@ -0,0 +1,554 @@
+use crate::combo::Combo;
+use crate::steno::{ActionSymbol, Command, UNDO_LIMIT};
+use log::warn;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Case {
+    Capitalize,
+    Lower,
+    Upper,
+}
+impl Case {
+    fn apply(&self, word: &str) -> String {
+        let mut chars = word.chars();
+        match (self, chars.next()) {
+            (_, None) => String::new(),
+            (Case::Capitalize, Some(c)) => c.to_uppercase().chain(chars).collect(),
+            (Case::Lower, Some(c)) => c.to_lowercase().chain(chars).collect(),
+            (Case::Upper, Some(_)) => word.to_uppercase(),
+        }
+    }
+}
+
+/// A translation split into unescaped literal text and the contents of its
+/// `{...}` operators.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Segment {
+    Text(String),
+    Meta(String),
+}
+impl Segment {
+    pub fn split(translation: &str) -> Vec<Segment> {
+        let mut segments = Vec::new();
+        let mut text = String::new();
+        let mut chars = translation.chars();
+        while let Some(c) = chars.next() {
+            match c {
+                '\\' => {
+                    if let Some(escaped) = chars.next() {
+                        text.push(escaped);
+                    }
+                }
+                '{' => {
+                    let meta: String = chars.by_ref().take_while(|&c| c != '}').collect();
+                    if !text.is_empty() {
+                        segments.push(Segment::Text(std::mem::take(&mut text)));
+                    }
+                    segments.push(Segment::Meta(meta));
+                }
+                _ => text.push(c),
+            }
+        }
+        if !text.is_empty() {
+            segments.push(Segment::Text(text));
+        }
+        segments
+    }
+}
+
+/// A piece of a dictionary translation, either plain words or one of the
+/// Plover `{...}` operators.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Atom {
+    /// Plain words, spaced from the previous output.
+    Text(String),
+    /// `{^ing}`, `{pre^}`, `{^}`: text joined to the output on the marked sides.
+    Attach {
+        text: String,
+        left: bool,
+        right: bool,
+    },
+    /// `{&a}`: joins onto a directly preceding glue stroke.
+    Glue(String),
+    /// `{.}`, `{,}` and friends: attached punctuation, sentence enders
+    /// capitalizing whatever follows.
+    Punctuation { text: String, capitalize: bool },
+    /// `{-|}`, `{>}`, `{<}`: the case of the next word.
+    Case(Case),
+    /// `{~|text}`: text that passes pending capitalization on to the next word.
+    Carry {
+        text: String,
+        left: bool,
+        right: bool,
+    },
+    /// `{#Control_L(c)}`: keys to press rather than text to type.
+    Combo(Vec<Combo>),
+    /// `{}`, `{PLOVER:...}` and anything else without output.
+    Noop,
+}
+impl Atom {
+    pub fn parse(translation: &str) -> Vec<Atom> {
+        Segment::split(translation)
+            .into_iter()
+            .filter_map(|segment| match segment {
+                Segment::Text(text) if text.trim().is_empty() => None,
+                Segment::Text(text) => Some(Atom::Text(text.trim().to_string())),
+                Segment::Meta(meta) => Some(Self::meta(&meta)),
+            })
+            .collect()
+    }
+
+    pub fn meta(meta: &str) -> Atom {
+        match meta {
+            "" => Atom::Noop,
+            "-|" => Atom::Case(Case::Capitalize),
+            ">" => Atom::Case(Case::Lower),
+            "<" => Atom::Case(Case::Upper),
+            "." | "?" | "!" => Atom::Punctuation {
+                text: meta.to_string(),
+                capitalize: true,
+            },
+            "," | ":" | ";" => Atom::Punctuation {
+                text: meta.to_string(),
+                capitalize: false,
+            },
+            m if m.starts_with('#') => match Combo::parse(&m[1..]) {
+                Ok(combos) => Atom::Combo(combos),
+                Err(e) => {
+                    warn!("{{{}}}: {}", m, e);
+                    Atom::Noop
+                }
+            },
+            m if m.starts_with("PLOVER:") || m.starts_with('*') => Atom::Noop,
+            m if m.starts_with('&') => Atom::Glue(m[1..].to_string()),
+            m if m.starts_with("~|") => {
+                let (text, left, right) = Self::attach(&m[2..]);
+                Atom::Carry { text, left, right }
+            }
+            m => {
+                let (text, left, right) = Self::attach(m);
+                Atom::Attach { text, left, right }
+            }
+        }
+    }
+
+    /// Split the `^` attach markers off either end of an operator.
+    fn attach(meta: &str) -> (String, bool, bool) {
+        if meta == "^" {
+            return (String::new(), true, true);
+        }
+        let left = meta.starts_with('^');
+        let right = meta.len() > 1 && meta.ends_with('^');
+        let text = meta.strip_prefix('^').unwrap_or(meta);
+        let text = if right { &text[..text.len() - 1] } else { text };
+        (text.to_string(), left, right)
+    }
+}
+
+/// What a translation sends, text to type and key combos in between.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Emit {
+    Text(String),
+    Combo(Vec<Combo>),
+}
+impl Emit {
+    /// Just the typed text of some output.
+    pub fn text(emits: &[Emit]) -> String {
+        emits
+            .iter()
+            .filter_map(|emit| match emit {
+                Emit::Text(text) => Some(text.as_str()),
+                Emit::Combo(_) => None,
+            })
+            .collect()
+    }
+}
+
+/// What the previous output left pending for the next one.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct State {
+    pub attach: bool,
+    pub case: Option<Case>,
+    pub glue: bool,
+}
+
+/// How many characters a translation typed, what it erased of the output
+/// before it, and the state it started from.
+#[derive(Clone, Debug)]
+struct Record {
+    typed: usize,
+    erased: String,
+    before: State,
+}
+
+/// How much of the recent output the formatter keeps to find the word a
+/// suffix attaches to.
+const TAIL_LIMIT: usize = 256;
+
+/// Characters to erase followed by output to send, built up over the
+/// translations a command writes.
+#[derive(Default)]
+struct Edit {
+    erase: usize,
+    emits: Vec<Emit>,
+}
+impl Edit {
+    /// Take back a character, from the output not sent yet when there is some.
+    fn backspace(&mut self) {
+        if let Some(Emit::Text(text)) = self.emits.last_mut() {
+            text.pop();
+            if text.is_empty() {
+                self.emits.pop();
+            }
+            return;
+        }
+        self.erase += 1;
+    }
+}
+
+fn vowel(c: char) -> bool {
+    "aeiou".contains(c.to_ascii_lowercase())
+}
+
+/// Plover's English orthography for a suffix attached to `word`: how many
+/// of its letters to erase and what to type instead of `suffix`.
+///
+/// - a consonant then `y`: `carry` + `s` is `carries`, + `ed` is `carried`
+/// - a sibilant: `box` + `s` is `boxes`
+/// - a silent `e` before a vowel: `make` + `ing` is `making`
+/// - one syllable ending consonant, vowel, consonant doubles the last:
+///   `run` + `ing` is `running`
+pub fn orthography(word: &str, suffix: &str) -> (usize, String) {
+    let letters: Vec<char> = word.to_lowercase().chars().collect();
+    let n = letters.len();
+    let first = match suffix.chars().next() {
+        Some(c) if n >= 2 && c.is_alphabetic() => c.to_ascii_lowercase(),
+        _ => return (0, suffix.to_string()),
+    };
+    let (last, before) = (letters[n - 1], letters[n - 2]);
+    let lower = suffix.to_lowercase();
+    if last == 'y' && !vowel(before) {
+        if lower == "s" {
+            return (1, "ies".to_string());
+        }
+        if vowel(first) && first != 'i' {
+            return (1, format!("i{}", suffix));
+        }
+    }
+    if lower == "s" && (matches!(last, 's' | 'x' | 'z') || (last == 'h' && matches!(before, 'c' | 's'))) {
+        return (0, format!("e{}", suffix));
+    }
+    if !vowel(first) {
+        return (0, suffix.to_string());
+    }
+    if last == 'e' && before != 'e' && n > 2 {
+        return (1, suffix.to_string());
+    }
+    let syllables = letters
+        .iter()
+        .zip(letters.iter().skip(1))
+        .filter(|(&a, &b)| !vowel(a) && vowel(b))
+        .count()
+        + vowel(letters[0]) as usize;
+    if syllables == 1 && n >= 3 && !vowel(last) && !"wxy".contains(last) && vowel(before) && !vowel(letters[n - 3]) {
+        let doubled = word.chars().last().unwrap_or(last);
+        return (0, format!("{}{}", doubled, suffix));
+    }
+    (0, suffix.to_string())
+}
+
+/// Turns dictionary translations into the text to type, keeping the spacing
+/// and capitalization state between strokes.
+#[derive(Default)]
+pub struct Formatter {
+    state: State,
+    records: Vec<Record>,
+    /// The end of the output so far, back to the last key combo.
+    tail: String,
+}
+impl Formatter {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Format a resolved command, returning what to erase and what to send.
+    /// A `Replace` erases the characters of the translations it replaces, an
+    /// `Undo` those of the last translation before retyping what it replaced.
+    /// Letters a suffix took off the word before are typed back with it.
+    pub fn apply(&mut self, command: &Command) -> (ActionSymbol, Vec<Emit>) {
+        let mut edit = Edit::default();
+        match command {
+            Command::Output(text) | Command::Error(text) => self.push(&mut edit, text),
+            Command::Replace(n, text) => {
+                let keep = self.records.len().saturating_sub(*n);
+                let replaced = self.records.split_off(keep);
+                if let Some(first) = replaced.first() {
+                    self.state = first.before;
+                }
+                self.revert(&mut edit, &replaced);
+                self.push(&mut edit, text);
+            }
+            Command::Undo(restored) => {
+                if let Some(record) = self.records.pop() {
+                    self.state = record.before;
+                    self.revert(&mut edit, &[record]);
+                }
+                for text in restored {
+                    self.push(&mut edit, text);
+                }
+            }
+        }
+        let erase = match edit.erase {
+            0 => ActionSymbol::Noop,
+            n => ActionSymbol::Replace(n),
+        };
+        (erase, edit.emits)
+    }
+
+    /// Take back the output of `records`, latest last, retyping whatever the
+    /// earliest of them erased.
+    fn revert(&mut self, edit: &mut Edit, records: &[Record]) {
+        let mut restore = String::new();
+        for record in records.iter().rev() {
+            let mut typed = record.typed;
+            while typed > 0 && restore.pop().is_some() {
+                typed -= 1;
+            }
+            edit.erase += typed;
+            restore.push_str(&record.erased);
+        }
+        let keep = self.tail.chars().count().saturating_sub(edit.erase);
+        self.tail = self.tail.chars().take(keep).collect();
+        self.tail.push_str(&restore);
+        if !restore.is_empty() {
+            edit.emits.push(Emit::Text(restore));
+        }
+    }
+
+    fn push(&mut self, edit: &mut Edit, translation: &str) {
+        let before = self.state;
+        let (erased, output) = self.format(translation);
+        for _ in erased.chars() {
+            edit.backspace();
+        }
+        self.records.push(Record {
+            typed: Emit::text(&output).chars().count(),
+            erased,
+            before,
+        });
+        if self.records.len() > UNDO_LIMIT {
+            self.records.remove(0);
+        }
+        edit.emits.extend(output);
+    }
+
+    /// Format a translation, returning the characters of earlier output its
+    /// suffixes erase and what to send.
+    pub fn format(&mut self, translation: &str) -> (String, Vec<Emit>) {
+        let mut erased = String::new();
+        let mut output = Vec::new();
+        let mut out = String::new();
+        for atom in Atom::parse(translation) {
+            let state = &mut self.state;
+            let space = if state.attach { "" } else { " " };
+            match atom {
+                Atom::Text(words) => {
+                    out.push_str(space);
+                    out.push_str(&Self::cased(state.case, &words));
+                    *state = State::default();
+                }
+                Atom::Attach { text, left, right } => {
+                    if !text.is_empty() {
+                        let text = Self::cased(state.case, &text);
+                        if left {
+                            let word = Self::last_word(&self.tail, &out);
+                            let (drop, suffix) = orthography(&word, &text);
+                            for _ in 0..drop {
+                                if out.pop().is_none() {
+                                    if let Some(c) = self.tail.pop() {
+                                        erased.insert(0, c);
+                                    }
+                                }
+                            }
+                            out.push_str(&suffix);
+                        } else {
+                            out.push_str(space);
+                            out.push_str(&text);
+                        }
+                        state.case = None;
+                    }
+                    state.attach = right;
+                    state.glue = false;
+                }
+                Atom::Glue(text) => {
+                    if !state.glue {
+                        out.push_str(space);
+                    }
+                    out.push_str(&Self::cased(state.case, &text));
+                    *state = State {
+                        glue: true,
+                        ..State::default()
+                    };
+                }
+                Atom::Punctuation { text, capitalize } => {
+                    out.push_str(&text);
+                    *state = State {
+                        case: if capitalize { Some(Case::Capitalize) } else { None },
+                        ..State::default()
+                    };
+                }
+                Atom::Case(case) => state.case = Some(case),
+                Atom::Carry { text, left, right } => {
+                    if !left {
+                        out.push_str(space);
+                    }
+                    out.push_str(&text);
+                    state.attach = right;
+                    state.glue = false;
+                }
+                Atom::Combo(combos) => {
+                    if !out.is_empty() {
+                        output.push(Emit::Text(std::mem::take(&mut out)));
+                    }
+                    output.push(Emit::Combo(combos));
+                    self.tail.clear();
+                }
+                Atom::Noop => {}
+            }
+        }
+        self.tail.push_str(&out);
+        let extra = self.tail.chars().count().saturating_sub(TAIL_LIMIT);
+        self.tail = self.tail.chars().skip(extra).collect();
+        if !out.is_empty() {
+            output.push(Emit::Text(out));
+        }
+        (erased, output)
+    }
+
+    /// The letters `out`, following `tail`, ends with.
+    fn last_word(tail: &str, out: &str) -> String {
+        let mut word: Vec<char> = tail
+            .chars()
+            .chain(out.chars())
+            .rev()
+            .take_while(|c| c.is_alphabetic())
+            .collect();
+        word.reverse();
+        word.into_iter().collect()
+    }
+
+    fn cased(case: Option<Case>, text: &str) -> String {
+        match case {
+            Some(case) => case.apply(text),
+            None => text.to_string(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use test_case::test_case;
+
+    /// What a run of translations leaves written.
+    fn typed(translations: &[&str]) -> String {
+        let mut formatter = Formatter::new();
+        let mut written = String::new();
+        for t in translations {
+            let (erase, output) = formatter.apply(&Command::Output(t.to_string()));
+            for _ in 0..erase.erased() {
+                written.pop();
+            }
+            written.push_str(&Emit::text(&output));
+        }
+        written
+    }
+
+    #[test_case("hello world", vec![Atom::Text("hello world".into())] ; "text")]
+    #[test_case("{^ing}", vec![Atom::Attach { text: "ing".into(), left: true, right: false }] ; "suffix")]
+    #[test_case("{pre^}", vec![Atom::Attach { text: "pre".into(), left: false, right: true }] ; "prefix")]
+    #[test_case("{^}", vec![Atom::Attach { text: "".into(), left: true, right: true }] ; "bare attach")]
+    #[test_case("{&a}", vec![Atom::Glue("a".into())] ; "glue")]
+    #[test_case("{.}", vec![Atom::Punctuation { text: ".".into(), capitalize: true }] ; "sentence end")]
+    #[test_case("{,}", vec![Atom::Punctuation { text: ",".into(), capitalize: false }] ; "comma")]
+    #[test_case("{-|}", vec![Atom::Case(Case::Capitalize)] ; "capitalize")]
+    #[test_case("{~|'^}", vec![Atom::Carry { text: "'".into(), left: false, right: true }] ; "carry")]
+    #[test_case("{#Control_L(c)}", vec![Atom::Combo(vec![Combo::Hold("Control_L".into(), vec![Combo::Press("c".into())])])] ; "combo")]
+    #[test_case("{PLOVER:ADD_TRANSLATION}", vec![Atom::Noop] ; "plover command")]
+    #[test_case("a {^}b", vec![Atom::Text("a".into()), Atom::Attach { text: "".into(), left: true, right: true }, Atom::Text("b".into())] ; "mixed")]
+    #[test_case("\\{x\\}", vec![Atom::Text("{x}".into())] ; "escaped braces")]
+    fn parse(translation: &str, atoms: Vec<Atom>) {
+        assert_eq!(Atom::parse(translation), atoms);
+    }
+
+    #[test_case(&["hello", "world"], " hello world" ; "spaced words")]
+    #[test_case(&["{-|}", "hello"], " Hello" ; "capitalize next")]
+    #[test_case(&["{<}", "shout"], " SHOUT" ; "upper next")]
+    #[test_case(&["hello", "{.}", "world"], " hello. World" ; "sentence end capitalizes")]
+    #[test_case(&["hello", "{,}", "world"], " hello, world" ; "comma attaches")]
+    #[test_case(&["{.}", "{>}", "World"], ". world" ; "lower overrides capitalize")]
+    #[test_case(&["run", "{^ing}"], " running" ; "suffix doubles a final consonant")]
+    #[test_case(&["open", "{^ing}"], " opening" ; "suffix after two syllables")]
+    #[test_case(&["cat", "{^s}"], " cats" ; "suffix attaches")]
+    #[test_case(&["carry", "{^s}"], " carries" ; "y to ies")]
+    #[test_case(&["carry", "{^ed}"], " carried" ; "y to i")]
+    #[test_case(&["carry", "{^ing}"], " carrying" ; "y kept before i")]
+    #[test_case(&["make", "{^ing}"], " making" ; "silent e dropped")]
+    #[test_case(&["box", "{^s}"], " boxes" ; "sibilant takes es")]
+    #[test_case(&["play", "{^s}"], " plays" ; "vowel y kept")]
+    #[test_case(&["{pre^}", "view"], " preview" ; "prefix attaches")]
+    #[test_case(&["a", "{^}", "b"], " ab" ; "bare attach")]
+    #[test_case(&["{&a}", "{&b}", "{&c}"], " abc" ; "glue joins glue")]
+    #[test_case(&["{&a}", "word", "{&b}"], " a word b" ; "glue spaced from words")]
+    #[test_case(&["{-|}", "{~|'^}", "word"], " 'Word" ; "carry passes capitalization on")]
+    fn apply(translations: &[&str], text: &str) {
+        assert_eq!(typed(translations), text);
+    }
+
+    #[test]
+    fn combo_splits_text() {
+        let mut formatter = Formatter::new();
+        let (_, output) = formatter.apply(&Command::Output("a{#Return}b".into()));
+        assert_eq!(
+            output,
+            vec![
+                Emit::Text(" a".into()),
+                Emit::Combo(vec![Combo::Press("Return".into())]),
+                Emit::Text(" b".into()),
+            ]
+        );
+    }
+
+    #[test]
+    fn suffix_erases_into_earlier_output() {
+        let mut formatter = Formatter::new();
+        formatter.apply(&Command::Output("carry".into()));
+        let (erase, output) = formatter.apply(&Command::Output("{^s}".into()));
+        assert_eq!(erase.erased(), 1);
+        assert_eq!(Emit::text(&output), "ies");
+        // undoing it types the erased letter back
+        let (erase, output) = formatter.apply(&Command::Undo(Vec::new()));
+        assert_eq!(erase.erased(), 3);
+        assert_eq!(Emit::text(&output), "y");
+        let (erase, output) = formatter.apply(&Command::Output("{^ed}".into()));
+        assert_eq!(erase.erased(), 1);
+        assert_eq!(Emit::text(&output), "ied");
+        // replacing both erases " carried" whole
+        let (erase, output) = formatter.apply(&Command::Replace(2, "cargo".into()));
+        assert_eq!(erase.erased(), 8);
+        assert_eq!(Emit::text(&output), " cargo");
+    }
+
+    #[test]
+    fn replace_and_undo_restore_state() {
+        let mut formatter = Formatter::new();
+        formatter.apply(&Command::Output("{-|}".into()));
+        formatter.apply(&Command::Output("a".into()));
+        let (erase, output) = formatter.apply(&Command::Replace(1, "ab".into()));
+        assert_eq!(erase.erased(), 2);
+        assert_eq!(Emit::text(&output), " Ab");
+        let (erase, output) = formatter.apply(&Command::Undo(vec!["a".into()]));
+        assert_eq!(erase.erased(), 3);
+        assert_eq!(Emit::text(&output), " A");
+    }
+}
@@ -0,0 +1,213 @@
+use crate::models::NewEntry;
+use crate::rtf;
+use crate::steno::Source;
+use log::info;
+use std::fmt;
+use std::fs;
+use std::path::Path;
+use std::str::FromStr;
+
+const CHUNK_SIZE: usize = 1000;
+
+/// What to do when an imported chord is already in the dictionary.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Conflict {
+    Keep,
+    Replace,
+}
+impl FromStr for Conflict {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "keep" => Ok(Self::Keep),
+            "replace" => Ok(Self::Replace),
+            _ => Err(format!("unknown conflict policy {}", s)),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum ImportError {
+    Io(std::io::Error),
+    Json(json::Error),
+    Database(diesel::result::Error),
+    Format(String),
+}
+impl fmt::Display for ImportError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Io(e) => write!(f, "{}", e),
+            Self::Json(e) => write!(f, "{}", e),
+            Self::Database(e) => write!(f, "{}", e),
+            Self::Format(e) => write!(f, "{}", e),
+        }
+    }
+}
+impl From<std::io::Error> for ImportError {
+    fn from(e: std::io::Error) -> Self {
+        Self::Io(e)
+    }
+}
+impl From<json::Error> for ImportError {
+    fn from(e: json::Error) -> Self {
+        Self::Json(e)
+    }
+}
+impl From<diesel::result::Error> for ImportError {
+    fn from(e: diesel::result::Error) -> Self {
+        Self::Database(e)
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct Report {
+    pub total: usize,
+    pub added: usize,
+    pub conflicts: usize,
+}
+
+/// Read a Plover JSON dictionary, an object of outlines to translations.
+pub fn read_json(path: &str) -> Result<Vec<NewEntry>, ImportError> {
+    let parsed = json::parse(&fs::read_to_string(path)?)?;
+    if !parsed.is_object() {
+        return Err(ImportError::Format(format!("{} is not a JSON object", path)));
+    }
+    parsed
+        .entries()
+        .map(|(chord, translation)| match translation.as_str() {
+            Some(translation) => Ok(NewEntry {
+                chord: chord.to_string(),
+                translation: translation.to_string(),
+            }),
+            None => Err(ImportError::Format(format!("{}: {} is not a string", path, chord))),
+        })
+        .collect()
+}
+
+/// Load entries in chunks, calling `progress` with the count done so far.
+pub fn load<F>(
+    dictionary: &Source,
+    entries: &[NewEntry],
+    conflict: Conflict,
+    mut progress: F,
+) -> Result<Report, ImportError>
+where
+    F: FnMut(usize, usize),
+{
+    let before = dictionary.len() as usize;
+    for (i, chunk) in entries.chunks(CHUNK_SIZE).enumerate() {
+        dictionary.insert(chunk, conflict)?;
+        progress(i * CHUNK_SIZE + chunk.len(), entries.len());
+    }
+    let added = (dictionary.len() as usize).saturating_sub(before);
+    Ok(Report {
+        total: entries.len(),
+        added,
+        conflicts: entries.len() - added,
+    })
+}
+
+fn is_rtf(path: &str) -> bool {
+    matches!(
+        Path::new(path).extension().and_then(|e| e.to_str()),
+        Some("rtf") | Some("RTF")
+    )
+}
+
+/// Read a dictionary file, RTF/CRE by its `.rtf` extension and Plover JSON
+/// otherwise.
+pub fn read(path: &str) -> Result<Vec<NewEntry>, ImportError> {
+    if is_rtf(path) {
+        Ok(rtf::parse(&String::from_utf8_lossy(&fs::read(path)?)))
+    } else {
+        read_json(path)
+    }
+}
+
+/// Import a dictionary file, reporting progress on stderr.
+pub fn file(dictionary: &Source, path: &str, conflict: Conflict) -> Result<Report, ImportError> {
+    let entries = read(path)?;
+    let report = load(dictionary, &entries, conflict, |done, total| {
+        eprint!("\rImporting {}: {}/{}", path, done, total);
+    })?;
+    eprintln!();
+    info!("Imported {}: {:?}", path, report);
+    Ok(report)
+}
+
+/// The database a JSON dictionary is kept in: `main.json` lives in `main.db`,
+/// reimported whenever the JSON file is newer. The reimport replaces clashing
+/// entries and leaves the rest, like those added since, in place.
+pub fn json_database(path: &str) -> Result<String, ImportError> {
+    let database = Path::new(path).with_extension("db");
+    let stale = match (fs::metadata(path)?.modified(), fs::metadata(&database)) {
+        (Ok(json), Ok(db)) => db.modified().map_or(true, |db| db < json),
+        _ => true,
+    };
+    let database = database.to_string_lossy().to_string();
+    if stale {
+        let source = Source::open(&database);
+        source.transaction(|| file(&source, path, Conflict::Replace))?;
+    }
+    Ok(database)
+}
+
+/// Write every entry out as RTF/CRE or, for any other extension, Plover JSON.
+pub fn export(dictionary: &Source, path: &str) -> Result<usize, ImportError> {
+    let entries = dictionary.entries();
+    let contents = if is_rtf(path) {
+        rtf::write(&entries)
+    } else {
+        let lines: Vec<String> = entries
+            .iter()
+            .map(|e| format!("{}: {}", json::stringify(e.chord.as_str()), json::stringify(e.translation.as_str())))
+            .collect();
+        format!("{{\n{}\n}}\n", lines.join(",\n"))
+    };
+    fs::write(path, contents)?;
+    info!("Exported {} entries to {}", entries.len(), path);
+    Ok(entries.len())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::{Duration, SystemTime};
+
+    #[test]
+    fn reimport_keeps_added_entries() {
+        let dir = std::env::temp_dir().join(format!("wayplover-import-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let json = dir.join("main.json");
+        let json = json.to_str().unwrap();
+        fs::write(json, r#"{"KAT": "cat", "TKOG": "dog"}"#).unwrap();
+        let database = json_database(json).unwrap();
+        let added = NewEntry {
+            chord: "PWEURD".into(),
+            translation: "bird".into(),
+        };
+        Source::open(&database).insert(&[added], Conflict::Replace).unwrap();
+
+        fs::write(json, r#"{"KAT": "cat!", "TKOG": "dog"}"#).unwrap();
+        let later = SystemTime::now() + Duration::from_secs(60);
+        fs::File::options().write(true).open(json).unwrap().set_modified(later).unwrap();
+        assert_eq!(json_database(json).unwrap(), database);
+
+        let mut entries: Vec<(String, String)> = Source::open(&database)
+            .entries()
+            .into_iter()
+            .map(|e| (e.chord, e.translation))
+            .collect();
+        entries.sort();
+        fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(
+            entries,
+            vec![
+                ("KAT".into(), "cat!".into()),
+                ("PWEURD".into(), "bird".into()),
+                ("SAP".into(), "sap".into()),
+                ("TKOG".into(), "dog".into()),
+            ]
+        );
+    }
+}
@@ -1,102 +1,97 @@
 #[macro_use]
 extern crate diesel;
+#[macro_use]
+extern crate diesel_migrations;
 extern crate sm;
 use clap::{App, Arg};
-pub(crate) use log::{debug, error, info, trace, warn};
+pub(crate) use log::{debug, info, warn};
 use std::fs;
-use fern;
-use chrono;
 use lazy_static::lazy_static;
 use maplit::hashmap;
 use std::collections::{HashMap, HashSet};
-use std::thread::sleep;
 use std::time::Duration;
+pub mod combo;
+pub mod daemon;
+pub mod drill;
+pub mod engine;
+pub mod format;
+pub mod import;
+// diesel 1.x derives expand to impls nested in consts
+#[allow(non_local_definitions)]
 pub mod models;
+pub mod output;
+pub mod rtf;
+#[allow(non_local_definitions)]
 pub mod schema;
+pub mod settings;
+pub mod socket;
+pub mod stats;
 pub mod steno;
+pub mod strokelog;
 pub mod ui;
 pub mod utils;
 mod workers;
+pub mod xkb;
 use evdev::{AttributeSet, Key};
 #[cfg(feature = "sound")]
 use workers::sound::AudioWorker;
-use workers::{serial::SerialWorker, window::InputWorker, Worker};
+use workers::{control::ControlWorker, keyboard::KeyboardWorker, serial::SerialWorker, window::InputWorker, Worker};
 
 const NAME: &str = env!("CARGO_CRATE_NAME");
 const VERSION: &str = env!("CARGO_PKG_VERSION");
 const AUTHORS: &str = env!("CARGO_PKG_AUTHORS");
 const DESCRIPTION: &str = env!("CARGO_PKG_DESCRIPTION");
 
+embed_migrations!();
+
 //todo move steno const to steno mod
 lazy_static! {
-    static ref KEY_CODE: HashMap<&'static str, (Option<Key>, Key)> = {
+    /// Lowercase keysym names of the keys key combos press that aren't
+    /// typed by the host layout's keymap.
+    static ref KEY_NAMES: HashMap<&'static str, Key> = {
         hashmap! {
-            "a" => (None, Key::KEY_A), "A" => (Some(Key::KEY_LEFTSHIFT), Key::KEY_A),
-            "b" => (None, Key::KEY_B), "B" => (Some(Key::KEY_LEFTSHIFT), Key::KEY_B),
-            "c" => (None, Key::KEY_C), "C" => (Some(Key::KEY_LEFTSHIFT), Key::KEY_C),
-            "d" => (None, Key::KEY_D), "D" => (Some(Key::KEY_LEFTSHIFT), Key::KEY_D),
-            "e" => (None, Key::KEY_E), "E" => (Some(Key::KEY_LEFTSHIFT), Key::KEY_E),
-            "f" => (None, Key::KEY_F), "F" => (Some(Key::KEY_LEFTSHIFT), Key::KEY_F),
-            "g" => (None, Key::KEY_G), "G" => (Some(Key::KEY_LEFTSHIFT), Key::KEY_G),
-            "h" => (None, Key::KEY_H), "H" => (Some(Key::KEY_LEFTSHIFT), Key::KEY_H),
-            "i" => (None, Key::KEY_I), "I" => (Some(Key::KEY_LEFTSHIFT), Key::KEY_I),
-            "j" => (None, Key::KEY_J), "J" => (Some(Key::KEY_LEFTSHIFT), Key::KEY_J),
-            "k" => (None, Key::KEY_K), "K" => (Some(Key::KEY_LEFTSHIFT), Key::KEY_K),
-            "l" => (None, Key::KEY_L), "L" => (Some(Key::KEY_LEFTSHIFT), Key::KEY_L),
-            "m" => (None, Key::KEY_M), "M" => (Some(Key::KEY_LEFTSHIFT), Key::KEY_M),
-            "n" => (None, Key::KEY_N), "N" => (Some(Key::KEY_LEFTSHIFT), Key::KEY_N),
-            "o" => (None, Key::KEY_O), "O" => (Some(Key::KEY_LEFTSHIFT), Key::KEY_O),
-            "p" => (None, Key::KEY_P), "P" => (Some(Key::KEY_LEFTSHIFT), Key::KEY_P),
-            "q" => (None, Key::KEY_Q), "Q" => (Some(Key::KEY_LEFTSHIFT), Key::KEY_Q),
-            "r" => (None, Key::KEY_R), "R" => (Some(Key::KEY_LEFTSHIFT), Key::KEY_R),
-            "s" => (None, Key::KEY_S), "S" => (Some(Key::KEY_LEFTSHIFT), Key::KEY_S),
-            "t" => (None, Key::KEY_T), "T" => (Some(Key::KEY_LEFTSHIFT), Key::KEY_T),
-            "u" => (None, Key::KEY_U), "U" => (Some(Key::KEY_LEFTSHIFT), Key::KEY_U),
-            "v" => (None, Key::KEY_V), "V" => (Some(Key::KEY_LEFTSHIFT), Key::KEY_V),
-            "w" => (None, Key::KEY_W), "W" => (Some(Key::KEY_LEFTSHIFT), Key::KEY_W),
-            "x" => (None, Key::KEY_X), "X" => (Some(Key::KEY_LEFTSHIFT), Key::KEY_X),
-            "y" => (None, Key::KEY_Y), "Y" => (Some(Key::KEY_LEFTSHIFT), Key::KEY_Y),
-            "z" => (None, Key::KEY_Z), "Z" => (Some(Key::KEY_LEFTSHIFT), Key::KEY_Z),
-            " " => (None, Key::KEY_SPACE),
-            "'" => (None, Key::KEY_APOSTROPHE), "\""=> (Some(Key::KEY_LEFTSHIFT), Key::KEY_APOSTROPHE)
+            "shift_l" => Key::KEY_LEFTSHIFT, "shift_r" => Key::KEY_RIGHTSHIFT,
+            "control_l" => Key::KEY_LEFTCTRL, "control_r" => Key::KEY_RIGHTCTRL,
+            "alt_l" => Key::KEY_LEFTALT, "alt_r" => Key::KEY_RIGHTALT,
+            "super_l" => Key::KEY_LEFTMETA, "super_r" => Key::KEY_RIGHTMETA,
+            "meta_l" => Key::KEY_LEFTMETA, "meta_r" => Key::KEY_RIGHTMETA,
+            "iso_level3_shift" => Key::KEY_RIGHTALT,
+            "return" => Key::KEY_ENTER, "kp_enter" => Key::KEY_KPENTER,
+            "tab" => Key::KEY_TAB, "iso_left_tab" => Key::KEY_TAB,
+            "backspace" => Key::KEY_BACKSPACE, "delete" => Key::KEY_DELETE,
+            "escape" => Key::KEY_ESC, "space" => Key::KEY_SPACE,
+            "insert" => Key::KEY_INSERT, "menu" => Key::KEY_COMPOSE,
+            "home" => Key::KEY_HOME, "end" => Key::KEY_END,
+            "page_up" => Key::KEY_PAGEUP, "prior" => Key::KEY_PAGEUP,
+            "page_down" => Key::KEY_PAGEDOWN, "next" => Key::KEY_PAGEDOWN,
+            "up" => Key::KEY_UP, "down" => Key::KEY_DOWN,
+            "left" => Key::KEY_LEFT, "right" => Key::KEY_RIGHT,
+            "caps_lock" => Key::KEY_CAPSLOCK, "num_lock" => Key::KEY_NUMLOCK,
+            "scroll_lock" => Key::KEY_SCROLLLOCK, "print" => Key::KEY_SYSRQ,
+            "pause" => Key::KEY_PAUSE,
+            "f1" => Key::KEY_F1, "f2" => Key::KEY_F2, "f3" => Key::KEY_F3,
+            "f4" => Key::KEY_F4, "f5" => Key::KEY_F5, "f6" => Key::KEY_F6,
+            "f7" => Key::KEY_F7, "f8" => Key::KEY_F8, "f9" => Key::KEY_F9,
+            "f10" => Key::KEY_F10, "f11" => Key::KEY_F11, "f12" => Key::KEY_F12,
+            "xf86audioraisevolume" => Key::KEY_VOLUMEUP,
+            "xf86audiolowervolume" => Key::KEY_VOLUMEDOWN,
+            "xf86audiomute" => Key::KEY_MUTE,
+            "xf86audioplay" => Key::KEY_PLAYPAUSE,
+            "xf86audiostop" => Key::KEY_STOPCD,
+            "xf86audionext" => Key::KEY_NEXTSONG,
+            "xf86audioprev" => Key::KEY_PREVIOUSSONG,
+            "xf86monbrightnessup" => Key::KEY_BRIGHTNESSUP,
+            "xf86monbrightnessdown" => Key::KEY_BRIGHTNESSDOWN,
         }
     };
+    /// Every key in `KEY_NAMES`, on top of whatever the host layout's keymap
+    /// types with.
     static ref VIRT_KEY_MAP: AttributeSet<Key> = {
-        key_set!(
-            Key::KEY_A,
-            Key::KEY_B,
-            Key::KEY_C,
-            Key::KEY_D,
-            Key::KEY_E,
-            Key::KEY_F,
-            Key::KEY_G,
-            Key::KEY_H,
-            Key::KEY_I,
-            Key::KEY_J,
-            Key::KEY_K,
-            Key::KEY_L,
-            Key::KEY_M,
-            Key::KEY_N,
-            Key::KEY_O,
-            Key::KEY_P,
-            Key::KEY_Q,
-            Key::KEY_R,
-            Key::KEY_S,
-            Key::KEY_T,
-            Key::KEY_U,
-            Key::KEY_V,
-            Key::KEY_W,
-            Key::KEY_X,
-            Key::KEY_Y,
-            Key::KEY_Z,
-            Key::KEY_APOSTROPHE,
-            Key::KEY_LEFTSHIFT,
-            Key::KEY_LEFTALT,
-            Key::KEY_LEFTCTRL,
-            Key::KEY_LEFTMETA,
-            Key::KEY_BACKSPACE,
-            Key::KEY_SPACE
-        )
+        let mut keys = key_set!(Key::KEY_LEFTSHIFT, Key::KEY_RIGHTALT);
+        for key in KEY_NAMES.values() {
+            keys.insert(*key);
+        }
+        keys
     };
     static ref STENO_ORDER: utils::OrderedMap<&'static str, i8, i8> = {
         ordered_map! {
@@ -180,32 +175,371 @@ fn main() {
                 .short("p")
                 .long("port")
                 .value_name("PORT")
-                .help("The device name ie /dev/ttyACM0."),
+                .help("The device name ie /dev/ttyACM0, the first serial device found when unset."),
+        )
+        .arg(
+            Arg::with_name("baud")
+                .long("baud")
+                .value_name("BAUD")
+                .validator(|s| s.parse::<u32>().map(|_| ()).map_err(|e| e.to_string()))
+                .help("The serial port's baud rate [default: 9600]."),
+        )
+        .arg(
+            Arg::with_name("data-bits")
+                .long("data-bits")
+                .value_name("BITS")
+                .possible_values(&["5", "6", "7", "8"])
+                .help("Data bits per character [default: 8]."),
+        )
+        .arg(
+            Arg::with_name("parity")
+                .long("parity")
+                .value_name("PARITY")
+                .possible_values(&["none", "odd", "even"])
+                .help("Parity checking [default: none]."),
+        )
+        .arg(
+            Arg::with_name("stop-bits")
+                .long("stop-bits")
+                .value_name("BITS")
+                .possible_values(&["1", "2"])
+                .help("Stop bits [default: 1]."),
+        )
+        .arg(
+            Arg::with_name("flow-control")
+                .long("flow-control")
+                .value_name("FLOW")
+                .possible_values(&["none", "software", "hardware"])
+                .help("Flow control [default: none]."),
+        )
+        .arg(
+            Arg::with_name("list-ports")
+                .long("list-ports")
+                .help("List the serial devices found and exit."),
+        )
+        .arg(
+            Arg::with_name("protocol")
+                .long("protocol")
+                .value_name("PROTOCOL")
+                .possible_values(&["gemini", "txbolt"])
+                .help("The protocol the steno machine speaks [default: gemini]."),
+        )
+        .arg(
+            Arg::with_name("config")
+                .short("c")
+                .long("config")
+                .value_name("FILE")
+                .help("A TOML config file instead of ~/.config/wayplover/config.toml."),
+        )
+        .arg(
+            Arg::with_name("profile")
+                .long("profile")
+                .value_name("NAME")
+                .help("Use a [profiles.NAME] table of the config file over its top level."),
+        )
+        .arg(
+            Arg::with_name("keyboard")
+                .short("k")
+                .long("keyboard")
+                .value_name("DEVICE")
+                .min_values(0)
+                .max_values(1)
+                .help("Steno from a keyboard, an evdev device or the first keyboard found."),
+        )
+        .arg(
+            Arg::with_name("keymap")
+                .long("keymap")
+                .value_name("FILE")
+                .requires("keyboard")
+                .help("A Plover style JSON keymap of steno keys to keyboard keys."),
+        )
+        .arg(
+            Arg::with_name("first-up")
+                .long("first-up")
+                .requires("keyboard")
+                .help("Send a keyboard chord when the first key is released."),
+        )
+        .arg(
+            Arg::with_name("headless")
+                .long("headless")
+                .help("Run without the TUI until SIGTERM or SIGINT, logging only."),
+        )
+        .arg(
+            Arg::with_name("control")
+                .long("control")
+                .value_name("SOCKET")
+                .help("Listen for line-delimited JSON commands on a Unix socket."),
+        )
+        .arg(
+            Arg::with_name("stats")
+                .long("stats")
+                .value_name("FILE")
+                .help("Append the session's statistics to a file as JSON lines on exit."),
+        )
+        .arg(
+            Arg::with_name("drill")
+                .long("drill")
+                .value_name("LESSON")
+                .help("Drill the words of a lesson file, one word or phrase per line."),
+        )
+        .arg(
+            Arg::with_name("drill-results")
+                .long("drill-results")
+                .value_name("FILE")
+                .requires("drill")
+                .help("Append the drill's results to a file as JSON lines on exit."),
+        )
+        .arg(
+            Arg::with_name("output")
+                .short("o")
+                .long("output")
+                .value_name("OUTPUT")
+                .validator(|s| s.parse::<output::Backend>().map(|_| ()))
+                .help("Where translations go: uinput, stdout, file:PATH or socket:PATH [default: uinput]."),
+        )
+        .arg(
+            Arg::with_name("layout")
+                .short("l")
+                .long("layout")
+                .value_name("LAYOUT")
+                .help("The XKB layout of the host keyboard ie de or us(dvorak) [default: us]."),
         )
         .arg(
             Arg::with_name("dictionary")
                 .short("d")
                 .long("dictionary")
                 .value_name("dictionary")
-                .help("The dictionary file to use."),
+                .multiple(true)
+                .number_of_values(1)
+                .help("A dictionary file to use, repeat for a stack where earlier ones take priority."),
+        )
+        .arg(
+            Arg::with_name("import")
+                .short("i")
+                .long("import")
+                .value_name("FILE")
+                .help("Import a Plover JSON or RTF/CRE dictionary into the dictionary database and exit."),
+        )
+        .arg(
+            Arg::with_name("export")
+                .short("e")
+                .long("export")
+                .value_name("FILE")
+                .conflicts_with("import")
+                .help("Export the dictionary database as RTF/CRE (.rtf) or Plover JSON and exit."),
+        )
+        .arg(
+            Arg::with_name("on-conflict")
+                .long("on-conflict")
+                .value_name("POLICY")
+                .possible_values(&["keep", "replace"])
+                .default_value("replace")
+                .help("Whether imported entries replace existing chords."),
         );
     init();
     let matches = app.get_matches();
-    let port = matches.value_of("port").unwrap_or("/dev/ttyACM0");
-    let path = matches.value_of("dictionary").unwrap_or("./main.json");
-    info!("-d {} -p {}", path, port);
+    // flags first, then the config file, then defaults
+    let settings = match settings::Settings::load(matches.value_of("config"), matches.value_of("profile")) {
+        Ok(settings) => settings,
+        Err(e) => {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        }
+    };
+    let settings = settings.overlay(settings::Settings {
+        protocol: matches.value_of("protocol").map(String::from),
+        port: matches.value_of("port").map(String::from),
+        baud: matches.value_of("baud").map(|baud| baud.parse().unwrap()),
+        data_bits: matches.value_of("data-bits").map(|bits| bits.parse().unwrap()),
+        parity: matches.value_of("parity").map(String::from),
+        stop_bits: matches.value_of("stop-bits").map(|bits| bits.parse().unwrap()),
+        flow_control: matches.value_of("flow-control").map(String::from),
+        dictionaries: matches
+            .values_of("dictionary")
+            .map_or_else(Vec::new, |paths| paths.map(String::from).collect()),
+        output: matches.value_of("output").map(String::from),
+        layout: matches.value_of("layout").map(String::from),
+        ..Default::default()
+    });
+    if matches.is_present("list-ports") {
+        for port in workers::serial::discover() {
+            println!("{}", port);
+        }
+        return;
+    }
+    let port = match &settings.port {
+        Some(port) => port.to_string(),
+        None => workers::serial::discover()
+            .into_iter()
+            .next()
+            .unwrap_or_else(|| "/dev/ttyACM0".to_string()),
+    };
+    let dictionaries: Vec<&str> = if settings.dictionaries.is_empty() {
+        vec!["./main.json"]
+    } else {
+        settings.dictionaries.iter().map(String::as_str).collect()
+    };
+    let paths: Vec<String> = dictionaries
+        .into_iter()
+        .map(|path| {
+            info!("-d {} -p {}", path, port);
+            if !path.ends_with(".json") {
+                return path.to_string();
+            }
+            match import::json_database(path) {
+                Ok(database) => database,
+                Err(e) => {
+                    eprintln!("{}: {}", path, e);
+                    std::process::exit(1);
+                }
+            }
+        })
+        .collect();
+    if let Some(file) = matches.value_of("import") {
+        let conflict = matches.value_of("on-conflict").unwrap().parse().unwrap();
+        match import::file(&steno::Source::open(&paths[0]), file, conflict) {
+            Ok(report) => println!(
+                "{}: {} entries, {} added, {} conflicting",
+                file, report.total, report.added, report.conflicts
+            ),
+            Err(e) => {
+                eprintln!("{}: {}", file, e);
+                std::process::exit(1);
+            }
+        }
+        return;
+    }
+    if let Some(file) = matches.value_of("export") {
+        match import::export(&steno::Source::open(&paths[0]), file) {
+            Ok(count) => println!("{}: {} entries", file, count),
+            Err(e) => {
+                eprintln!("{}: {}", file, e);
+                std::process::exit(1);
+            }
+        }
+        return;
+    }
+    let keyboard = if matches.is_present("keyboard") {
+        let layout = match matches.value_of("keymap") {
+            Some(path) => match workers::keyboard::Layout::from_file(path) {
+                Ok(layout) => layout,
+                Err(e) => {
+                    eprintln!("{}", e);
+                    std::process::exit(1);
+                }
+            },
+            None => Default::default(),
+        };
+        Some(workers::keyboard::Options {
+            device: matches.value_of("keyboard").map(String::from),
+            layout,
+            mode: if matches.is_present("first-up") {
+                workers::keyboard::ChordMode::FirstUp
+            } else {
+                workers::keyboard::ChordMode::AllUp
+            },
+        })
+    } else {
+        None
+    };
+    let layout = settings.layout.as_deref().unwrap_or("us");
+    let key_delay = Duration::from_millis(settings.key_delay.unwrap_or(1));
+    let backend = settings
+        .output
+        .as_deref()
+        .unwrap_or("uinput")
+        .parse::<output::Backend>()
+        .and_then(|backend| backend.open(layout, key_delay));
+    let backend = match backend {
+        Ok(backend) => backend,
+        Err(e) => {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        }
+    };
+    let protocol = settings.protocol.as_deref().unwrap_or("gemini").parse();
+    let protocol = match protocol {
+        Ok(protocol) => protocol,
+        Err(e) => {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        }
+    };
+    let panes = match &settings.panes {
+        Some(panes) => match panes.iter().map(|p| p.parse()).collect() {
+            Ok(panes) => panes,
+            Err(e) => {
+                eprintln!("{}", e);
+                std::process::exit(1);
+            }
+        },
+        None => ui::Options::default().panes,
+    };
+    let options = ui::Options {
+        port: port.to_string(),
+        refresh: settings.refresh.map_or(ui::Options::default().refresh, Duration::from_millis),
+        panes,
+    };
+    let line = workers::serial::port_settings(
+        settings.baud.unwrap_or(9600),
+        settings.data_bits.unwrap_or(8),
+        settings.parity.as_deref().unwrap_or("none"),
+        settings.stop_bits.unwrap_or(1),
+        settings.flow_control.as_deref().unwrap_or("none"),
+    );
+    let line = match line {
+        Ok(line) => line,
+        Err(e) => {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        }
+    };
     let config = workers::Config {
-        tick_rate: Duration::from_millis(5),
+        tick_rate: Duration::from_millis(settings.tick_rate.unwrap_or(5)),
         port: port.to_string(),
+        line,
+        protocol,
+        keyboard,
+        control: matches.value_of("control").map(String::from),
+        sound: settings.sound.unwrap_or(true),
     };
+    let headless = matches.is_present("headless");
     let worker_pool = workers::WorkerPool {
         #[cfg(feature = "sound")]
         audio: AudioWorker::start(config.clone()),
         serial: SerialWorker::start(config.clone()),
-        window: InputWorker::start(config.clone()),
+        keyboard: config.keyboard.as_ref().map(|_| KeyboardWorker::start(config.clone())),
+        control: config.control.as_ref().map(|_| ControlWorker::start(config.clone())),
+        window: if headless {
+            None
+        } else {
+            Some(InputWorker::start(config.clone()))
+        },
     };
-    let dictionary = steno::Dictionary::from_file(path);
-    
-    let mut ui = ui::Tui::new(worker_pool, dictionary);
+    let mut engine = engine::Engine::new(steno::Dictionary::from_files(&paths), backend);
+    engine.stats.export = matches.value_of("stats").map(String::from);
+    if let Some(path) = strokelog::StrokeLog::path() {
+        match strokelog::StrokeLog::open(&path.to_string_lossy(), &engine.session) {
+            Ok(log) => engine.log = Some(log),
+            Err(e) => warn!("Not logging strokes: {}", e),
+        }
+    }
+    if let Some(lesson) = matches.value_of("drill") {
+        match drill::Drill::from_file(lesson, &engine.dictionary) {
+            Ok(mut drill) => {
+                drill.export = matches.value_of("drill-results").map(String::from);
+                engine.drill = Some(drill);
+            }
+            Err(e) => {
+                eprintln!("{}", e);
+                std::process::exit(1);
+            }
+        }
+    }
+    if headless {
+        daemon::run(worker_pool, engine, config.tick_rate);
+        return;
+    }
+    let mut ui = ui::Tui::new(worker_pool, engine, options);
     ui.run();
 }
@@ -1,5 +1,5 @@
-use crate::schema::dictionary;
-#[derive(Queryable, Debug)]
+use crate::schema::{dictionary, strokes};
+#[derive(Queryable, Clone, Debug)]
 pub struct Entry {
     pub id: i32,
     pub chord: String,
@@ -11,3 +11,23 @@ pub struct NewEntry {
     pub chord: String,
     pub translation: String,
 }
+#[derive(Queryable, Clone, Debug)]
+pub struct Stroke {
+    pub id: i32,
+    pub session: String,
+    pub timestamp: i64,
+    pub raw: String,
+    pub chord: String,
+    pub translation: Option<String>,
+    pub undone: bool,
+}
+#[derive(Insertable)]
+#[table_name = "strokes"]
+pub struct NewStroke {
+    pub session: String,
+    pub timestamp: i64,
+    pub raw: String,
+    pub chord: String,
+    pub translation: Option<String>,
+    pub undone: bool,
+}
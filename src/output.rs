@@ -0,0 +1,304 @@
+use crate::combo::Combo;
+use crate::format::Emit;
+use crate::steno::ActionSymbol;
+use crate::{socket, warn, xkb, KEY_NAMES, VIRT_KEY_MAP};
+use evdev::{uinput, Key as VirtualKey};
+use std::fs;
+use std::io::{self, Write};
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::str::FromStr;
+use std::thread::sleep;
+use std::time::Duration;
+
+/// Where translations are sent.
+pub trait Output {
+    /// Erase what the command replaces, then send its formatted output.
+    fn send(&mut self, erase: ActionSymbol, emits: Vec<Emit>);
+}
+
+/// The output backends to pick from the command line.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Backend {
+    Uinput,
+    Stdout,
+    File(String),
+    Socket(String),
+}
+impl FromStr for Backend {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.split_once(':') {
+            None if s == "uinput" => Ok(Self::Uinput),
+            None if s == "stdout" => Ok(Self::Stdout),
+            Some(("file", path)) if !path.is_empty() => Ok(Self::File(path.to_string())),
+            Some(("socket", path)) if !path.is_empty() => Ok(Self::Socket(path.to_string())),
+            _ => Err(format!("unknown output {}, expected uinput, stdout, file:PATH or socket:PATH", s)),
+        }
+    }
+}
+impl Backend {
+    /// Open the backend, typing for the XKB `layout` with `delay` between
+    /// key events when it is uinput. Without the XKB data files the default
+    /// `us` layout falls back to a built-in one.
+    pub fn open(&self, layout: &str, delay: Duration) -> Result<Box<dyn Output>, String> {
+        Ok(match self {
+            Self::Uinput => {
+                let keymap = match xkb::Keymap::new(layout) {
+                    Ok(keymap) => keymap,
+                    Err(e) if layout == "us" => {
+                        warn!("{}, using the built-in us layout", e);
+                        xkb::Keymap::us()
+                    }
+                    Err(e) => return Err(e),
+                };
+                Box::new(Uinput::new(keymap, delay).map_err(|e| format!("/dev/uinput: {}", e))?)
+            }
+            Self::Stdout => Box::new(Writer::new(io::stdout())),
+            Self::File(path) => Box::new(Writer::new(
+                fs::OpenOptions::new()
+                    .create(true)
+                    .append(true)
+                    .open(path)
+                    .map_err(|e| format!("{}: {}", path, e))?,
+            )),
+            Self::Socket(path) => Box::new(Socket::bind(path).map_err(|e| format!("{}: {}", path, e))?),
+        })
+    }
+}
+
+/// The text stream backends send: a backspace for each character erased, the
+/// typed text, and key combos in their `{#...}` form.
+fn render(erase: ActionSymbol, emits: &[Emit]) -> String {
+    let mut out = "\x08".repeat(erase.erased());
+    for emit in emits {
+        match emit {
+            Emit::Text(text) => out.push_str(text),
+            Emit::Combo(combos) => {
+                let combos: Vec<String> = combos.iter().map(Combo::to_string).collect();
+                out.push_str(&format!("{{#{}}}", combos.join(" ")));
+            }
+        }
+    }
+    out
+}
+
+/// Writes the text stream to stdout or appends it to a file.
+pub struct Writer<W: Write> {
+    writer: W,
+}
+impl<W: Write> Writer<W> {
+    pub fn new(writer: W) -> Self {
+        Self { writer }
+    }
+}
+impl<W: Write> Output for Writer<W> {
+    fn send(&mut self, erase: ActionSymbol, emits: Vec<Emit>) {
+        let out = render(erase, &emits);
+        if let Err(e) = self.writer.write_all(out.as_bytes()).and_then(|_| self.writer.flush()) {
+            warn!("{}", e);
+        }
+    }
+}
+
+/// Listens on a Unix socket and writes the text stream to every connected
+/// client. Clients are written to without blocking, one that falls so far
+/// behind its socket buffer fills is dropped.
+pub struct Socket {
+    path: String,
+    listener: UnixListener,
+    clients: Vec<UnixStream>,
+}
+impl Socket {
+    pub fn bind(path: &str) -> io::Result<Self> {
+        let listener = socket::listen(path)?;
+        Ok(Self {
+            path: path.to_string(),
+            listener,
+            clients: Vec::new(),
+        })
+    }
+}
+impl Output for Socket {
+    fn send(&mut self, erase: ActionSymbol, emits: Vec<Emit>) {
+        while let Ok((client, _)) = self.listener.accept() {
+            match client.set_nonblocking(true) {
+                Ok(()) => self.clients.push(client),
+                Err(e) => warn!("{}: {}", self.path, e),
+            }
+        }
+        let out = render(erase, &emits);
+        self.clients.retain_mut(|client| client.write_all(out.as_bytes()).is_ok());
+    }
+}
+impl Drop for Socket {
+    fn drop(&mut self) {
+        socket::remove(&self.path);
+    }
+}
+
+/// A key going down and coming straight back up, or only one of the two for
+/// keys held around others.
+#[derive(Clone, Copy, Debug)]
+enum KeyEvent {
+    Press(VirtualKey),
+    Down(VirtualKey),
+    Up(VirtualKey),
+}
+/// The virtual `wayplover` keyboard.
+pub struct Uinput {
+    device: uinput::VirtualDevice,
+    keymap: xkb::Keymap,
+    /// Between key events, some applications drop keys sent faster.
+    delay: Duration,
+}
+impl Uinput {
+    pub fn new(keymap: xkb::Keymap, delay: Duration) -> io::Result<Self> {
+        let mut keys = VIRT_KEY_MAP.clone();
+        for key in keymap.keys() {
+            keys.insert(key);
+        }
+        let device = uinput::VirtualDeviceBuilder::new()?
+            .name("wayplover")
+            .with_keys(&keys)?
+            .build()?;
+        Ok(Self { device, keymap, delay })
+    }
+    fn create_stream(&self, cmd: ActionSymbol, emits: Vec<Emit>) -> Vec<KeyEvent> {
+        let mut temp = vec![KeyEvent::Press(VirtualKey::KEY_BACKSPACE); cmd.erased()];
+        for emit in emits {
+            match emit {
+                Emit::Text(text) => {
+                    for c in text.chars() {
+                        match self.keymap.get(c) {
+                            Some(strokes) => Self::extend(&mut temp, strokes),
+                            None => self.unicode(&mut temp, c),
+                        }
+                    }
+                }
+                Emit::Combo(combos) => self.combo(&mut temp, &combos),
+            }
+        }
+        temp
+    }
+    fn extend(temp: &mut Vec<KeyEvent>, strokes: &[xkb::Stroke]) {
+        for stroke in strokes {
+            temp.extend(stroke.modifiers.iter().map(|&m| KeyEvent::Down(m)));
+            temp.push(KeyEvent::Press(stroke.key));
+            temp.extend(stroke.modifiers.iter().rev().map(|&m| KeyEvent::Up(m)));
+        }
+    }
+    /// Press the keys of a key combo, holding modifiers around their part.
+    fn combo(&self, temp: &mut Vec<KeyEvent>, combos: &[Combo]) {
+        for combo in combos {
+            let name = match combo {
+                Combo::Press(name) | Combo::Hold(name, _) => name,
+            };
+            let key = match KEY_NAMES.get(name.to_lowercase().as_str()) {
+                Some(&key) => key,
+                None => match self.keymap.keysym(name) {
+                    Some(key) => key,
+                    None => {
+                        warn!("No key for {} in the layout", name);
+                        continue;
+                    }
+                },
+            };
+            match combo {
+                Combo::Press(_) => temp.push(KeyEvent::Press(key)),
+                Combo::Hold(_, inner) => {
+                    temp.push(KeyEvent::Down(key));
+                    self.combo(temp, inner);
+                    temp.push(KeyEvent::Up(key));
+                }
+            }
+        }
+    }
+    /// Type a character the layout has no key for with the Ctrl+Shift+U
+    /// unicode entry sequence.
+    fn unicode(&self, temp: &mut Vec<KeyEvent>, c: char) {
+        let u = self.keymap.keysym("u").unwrap_or(VirtualKey::KEY_U);
+        temp.extend(vec![
+            KeyEvent::Down(VirtualKey::KEY_LEFTCTRL),
+            KeyEvent::Down(VirtualKey::KEY_LEFTSHIFT),
+            KeyEvent::Press(u),
+            KeyEvent::Up(VirtualKey::KEY_LEFTSHIFT),
+            KeyEvent::Up(VirtualKey::KEY_LEFTCTRL),
+        ]);
+        for digit in format!("{:x} ", c as u32).chars() {
+            if let Some(strokes) = self.keymap.get(digit) {
+                Self::extend(temp, strokes);
+            }
+        }
+    }
+    fn emit(&mut self, seq: Vec<KeyEvent>) {
+        use evdev::{EventType, InputEvent};
+        for event in seq {
+            let events = match event {
+                KeyEvent::Press(key) => vec![
+                    InputEvent::new(EventType::KEY, key.code(), 1),
+                    InputEvent::new(EventType::KEY, key.code(), 0),
+                ],
+                KeyEvent::Down(key) => vec![InputEvent::new(EventType::KEY, key.code(), 1)],
+                KeyEvent::Up(key) => vec![InputEvent::new(EventType::KEY, key.code(), 0)],
+            };
+            if let Err(e) = self.device.emit(&events) {
+                warn!("/dev/uinput: {}, dropping the rest of the output", e);
+                return;
+            }
+            sleep(self.delay);
+        }
+    }
+}
+
+impl Output for Uinput {
+    fn send(&mut self, erase: ActionSymbol, emits: Vec<Emit>) {
+        let stream = self.create_stream(erase, emits);
+        self.emit(stream);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::combo::Combo;
+    use std::io::Read;
+
+    #[test]
+    fn writer_renders_erase_text_and_combos() {
+        let mut writer = Writer::new(Vec::new());
+        writer.send(ActionSymbol::Noop, vec![Emit::Text(" cat".into())]);
+        writer.send(ActionSymbol::Replace(1), vec![Emit::Text("ies".into())]);
+        writer.send(
+            ActionSymbol::Replace(2),
+            vec![
+                Emit::Text(" a".into()),
+                Emit::Combo(vec![Combo::Hold("Control_L".into(), vec![Combo::Press("c".into())])]),
+            ],
+        );
+        assert_eq!(
+            String::from_utf8(writer.writer).unwrap(),
+            " cat\x08ies\x08\x08 a{#Control_L(c)}"
+        );
+    }
+
+    #[test]
+    fn socket_drops_stalled_clients() {
+        let path = std::env::temp_dir().join(format!("wayplover-output-{}.sock", std::process::id()));
+        let path = path.to_str().unwrap();
+        let mut socket = Socket::bind(path).unwrap();
+        let mut reader = UnixStream::connect(path).unwrap();
+        let _stalled = UnixStream::connect(path).unwrap();
+        socket.send(ActionSymbol::Noop, vec![Emit::Text(" hello".into())]);
+        assert_eq!(socket.clients.len(), 2);
+        let mut hello = [0; 6];
+        reader.read_exact(&mut hello).unwrap();
+        assert_eq!(&hello, b" hello");
+
+        // far more than a socket buffer, neither client reading
+        let chunk = "x".repeat(64 * 1024);
+        for _ in 0..64 {
+            socket.send(ActionSymbol::Noop, vec![Emit::Text(chunk.clone())]);
+        }
+        assert!(socket.clients.is_empty());
+    }
+}
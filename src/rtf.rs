@@ -0,0 +1,350 @@
+use crate::format::{Atom, Case, Segment};
+use crate::models::{Entry, NewEntry};
+
+#[derive(Clone, Debug, PartialEq)]
+enum Token {
+    Open,
+    Close,
+    Control(String, Option<i32>),
+    Symbol(char),
+    Text(String),
+}
+
+fn tokenize(rtf: &str) -> Vec<Token> {
+    let mut tokens = Vec::new();
+    let mut text = String::new();
+    let mut chars = rtf.chars().peekable();
+    let flush = |text: &mut String, tokens: &mut Vec<Token>| {
+        if !text.is_empty() {
+            tokens.push(Token::Text(std::mem::take(text)));
+        }
+    };
+    while let Some(c) = chars.next() {
+        match c {
+            '{' | '}' => {
+                flush(&mut text, &mut tokens);
+                tokens.push(if c == '{' { Token::Open } else { Token::Close });
+            }
+            '\\' => {
+                flush(&mut text, &mut tokens);
+                match chars.peek().copied() {
+                    Some(l) if l.is_ascii_alphabetic() => {
+                        let mut word = String::new();
+                        while let Some(&l) = chars.peek().filter(|l| l.is_ascii_alphabetic()) {
+                            word.push(l);
+                            chars.next();
+                        }
+                        let mut number = String::new();
+                        while let Some(&d) = chars
+                            .peek()
+                            .filter(|d| d.is_ascii_digit() || (number.is_empty() && **d == '-'))
+                        {
+                            number.push(d);
+                            chars.next();
+                        }
+                        // a single space only delimits the control word
+                        if chars.peek() == Some(&' ') {
+                            chars.next();
+                        }
+                        tokens.push(Token::Control(word, number.parse().ok()));
+                    }
+                    Some('\'') => {
+                        chars.next();
+                        let hex: String = chars.by_ref().take(2).collect();
+                        if let Ok(byte) = u8::from_str_radix(&hex, 16) {
+                            text.push(char::from(byte));
+                        }
+                    }
+                    Some(s) => {
+                        chars.next();
+                        tokens.push(Token::Symbol(s));
+                    }
+                    None => {}
+                }
+            }
+            '\r' | '\n' => {}
+            _ => text.push(c),
+        }
+    }
+    flush(&mut text, &mut tokens);
+    tokens
+}
+
+/// Index of the token closing the group opened just before `start`.
+fn group_end(tokens: &[Token], start: usize) -> usize {
+    let mut depth = 1;
+    for (i, token) in tokens.iter().enumerate().skip(start) {
+        match token {
+            Token::Open => depth += 1,
+            Token::Close => {
+                depth -= 1;
+                if depth == 0 {
+                    return i;
+                }
+            }
+            _ => {}
+        }
+    }
+    tokens.len()
+}
+
+fn plain(tokens: &[Token]) -> String {
+    tokens
+        .iter()
+        .filter_map(|t| match t {
+            Token::Text(s) => Some(s.to_owned()),
+            Token::Symbol(c) if "\\{}".contains(*c) => Some(c.to_string()),
+            _ => None,
+        })
+        .collect()
+}
+
+fn escape(text: &str) -> String {
+    text.chars()
+        .map(|c| match c {
+            '\\' | '{' | '}' => format!("\\{}", c),
+            '\n' => "\\par ".to_string(),
+            '\t' => "\\tab ".to_string(),
+            c if c.is_ascii() => c.to_string(),
+            c => c
+                .encode_utf16(&mut [0; 2])
+                .iter()
+                .map(|&unit| format!("\\u{}?", unit as i16))
+                .collect(),
+        })
+        .collect()
+}
+
+/// Escape literal text back into the Plover syntax.
+fn plover_escape(text: &str) -> String {
+    text.chars()
+        .map(|c| match c {
+            '\\' | '{' | '}' => format!("\\{}", c),
+            c => c.to_string(),
+        })
+        .collect()
+}
+
+/// Fold the bare `{^}` markers `\cxds` leaves around a single word into it.
+fn attach(translation: String) -> String {
+    let left = translation.strip_prefix("{^}");
+    let body = left.unwrap_or(&translation);
+    let right = body.strip_suffix("{^}");
+    let body = right.unwrap_or(body);
+    if body.is_empty() || body.contains(|c: char| c.is_whitespace() || "{}\\".contains(c)) {
+        return translation;
+    }
+    match (left.is_some(), right.is_some()) {
+        (true, true) => format!("{{^{}^}}", body),
+        (true, false) => format!("{{^{}}}", body),
+        (false, true) => format!("{{{}^}}", body),
+        (false, false) => translation,
+    }
+}
+
+/// Translate the tokens of one entry into the Plover syntax.
+fn translate(tokens: &[Token]) -> String {
+    let mut out = String::new();
+    let mut units: Vec<u16> = Vec::new();
+    let mut i = 0;
+    while i < tokens.len() {
+        match &tokens[i] {
+            Token::Open => {
+                let end = group_end(tokens, i + 1);
+                let group = &tokens[i + 1..end];
+                match group.first() {
+                    Some(Token::Control(word, _)) if word == "cxp" => {
+                        let punctuation = plain(&group[1..]).trim().to_string();
+                        match punctuation.as_str() {
+                            "." | "," | "?" | "!" | ":" | ";" => {
+                                out.push_str(&format!("{{{}}}", punctuation))
+                            }
+                            p => out.push_str(&format!("{{^{}^}}", plover_escape(p))),
+                        }
+                    }
+                    Some(Token::Control(word, _)) if word == "cxfing" => {
+                        out.push_str(&format!("{{&{}}}", plover_escape(plain(&group[1..]).trim())))
+                    }
+                    // other destinations such as comments carry no translation
+                    Some(Token::Symbol('*')) => {
+                        if let Some(Token::Control(word, _)) = group.get(1) {
+                            if word == "cxplovermeta" {
+                                out.push_str(&format!("{{{}}}", plain(&group[2..])));
+                            }
+                        }
+                    }
+                    _ => out.push_str(&translate(group)),
+                }
+                i = end;
+            }
+            Token::Control(word, number) => match word.as_str() {
+                "cxds" => out.push_str("{^}"),
+                "cxfc" => out.push_str("{-|}"),
+                "cxfl" => out.push_str("{>}"),
+                "par" => out.push_str("{^\n^}"),
+                "tab" => out.push_str("{^\t^}"),
+                "u" => {
+                    // surrogate pairs come as two \u words
+                    units.extend(number.map(|n| n as u16));
+                    if let Ok(decoded) = String::from_utf16(&units) {
+                        out.push_str(&plover_escape(&decoded));
+                        units.clear();
+                    }
+                    // skip the ANSI fallback character
+                    if let Some(Token::Text(s)) = tokens.get(i + 1) {
+                        out.push_str(&plover_escape(&s.chars().skip(1).collect::<String>()));
+                        i += 1;
+                    }
+                }
+                _ => {}
+            },
+            Token::Symbol(c) => match c {
+                '\\' | '{' | '}' => out.push_str(&format!("\\{}", c)),
+                '~' => out.push(' '),
+                '_' => out.push('-'),
+                _ => {}
+            },
+            Token::Text(s) => out.push_str(&plover_escape(s)),
+            Token::Close => {}
+        }
+        i += 1;
+    }
+    out
+}
+
+/// Read the entries of an RTF/CRE dictionary, each `{\*\cxs STROKES}` group
+/// followed by its translation.
+pub fn parse(rtf: &str) -> Vec<NewEntry> {
+    let tokens = tokenize(rtf);
+    // everything inside the outer {\rtf1 ...} group
+    let body = match tokens.iter().position(|t| *t == Token::Open) {
+        Some(start) => &tokens[start + 1..group_end(&tokens, start + 1)],
+        None => &tokens[..],
+    };
+    // (chord, where its group starts, where its translation starts)
+    let mut strokes = Vec::new();
+    let mut i = 0;
+    while i < body.len() {
+        if body[i] != Token::Open {
+            i += 1;
+            continue;
+        }
+        let end = group_end(body, i + 1);
+        if let [Token::Symbol('*'), Token::Control(word, _), rest @ ..] = &body[i + 1..end] {
+            if word == "cxs" {
+                strokes.push((plain(rest).trim().to_string(), i, end + 1));
+            }
+        }
+        i = end + 1;
+    }
+    strokes
+        .iter()
+        .enumerate()
+        .map(|(k, (chord, _, start))| {
+            let stop = strokes.get(k + 1).map_or(body.len(), |next| next.1);
+            NewEntry {
+                chord: chord.to_owned(),
+                translation: attach(translate(&body[*start.min(&stop)..stop]).trim().to_string()),
+            }
+        })
+        .collect()
+}
+
+fn write_translation(translation: &str) -> String {
+    let mut out = String::new();
+    for segment in Segment::split(translation) {
+        match segment {
+            Segment::Text(text) => out.push_str(&escape(&text)),
+            Segment::Meta(meta) => out.push_str(&match Atom::meta(&meta) {
+                Atom::Attach { text, .. } if text == "\n" => "\\par ".to_string(),
+                Atom::Attach { text, left, right } if text.is_empty() && (left || right) => {
+                    "\\cxds ".to_string()
+                }
+                Atom::Attach { text, left, right } if left || right => format!(
+                    "{}{}{}",
+                    if left { "\\cxds " } else { "" },
+                    escape(&text),
+                    if right { "\\cxds " } else { "" }
+                ),
+                Atom::Glue(text) => format!("{{\\cxfing {}}}", escape(&text)),
+                Atom::Punctuation { text, .. } => format!("{{\\cxp{} }}", text),
+                Atom::Case(Case::Capitalize) => "\\cxfc ".to_string(),
+                Atom::Case(Case::Lower) => "\\cxfl ".to_string(),
+                _ => format!("{{\\*\\cxplovermeta {}}}", escape(&meta)),
+            }),
+        }
+    }
+    out
+}
+
+/// Write entries as an RTF/CRE dictionary.
+pub fn write(entries: &[Entry]) -> String {
+    let mut rtf = String::from(
+        "{\\rtf1\\ansi{\\*\\cxrev100}\\cxdict{\\*\\cxsystem wayplover}{\\stylesheet{\\s0 Normal;}}\r\n",
+    );
+    for e in entries {
+        rtf.push_str(&format!(
+            "{{\\*\\cxs {}}}{}\r\n",
+            escape(&e.chord),
+            write_translation(&e.translation)
+        ));
+    }
+    rtf.push_str("}\r\n");
+    rtf
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use test_case::test_case;
+
+    #[test_case("cat" ; "text")]
+    #[test_case("{^ing}" ; "suffix")]
+    #[test_case("{pre^}" ; "prefix")]
+    #[test_case("{^-^}" ; "infix")]
+    #[test_case("{^}" ; "bare attach")]
+    #[test_case("{&a}" ; "glue")]
+    #[test_case("{.}" ; "period")]
+    #[test_case("{,}" ; "comma")]
+    #[test_case("{-|}" ; "capitalize")]
+    #[test_case("{>}" ; "lower")]
+    #[test_case("{^\n^}" ; "paragraph")]
+    #[test_case("{#Control_L(c)}" ; "plover meta")]
+    #[test_case("café naïve" ; "unicode")]
+    #[test_case("\u{1f600}" ; "surrogate pair")]
+    #[test_case("\\{x\\}" ; "escaped braces")]
+    #[test_case("{-|}new york" ; "mixed")]
+    fn round_trip(translation: &str) {
+        let entries = [Entry {
+            id: 1,
+            chord: "KAT/-S".into(),
+            translation: translation.into(),
+        }];
+        let parsed = parse(&write(&entries));
+        assert_eq!(parsed.len(), 1);
+        assert_eq!(parsed[0].chord, "KAT/-S");
+        assert_eq!(parsed[0].translation, translation);
+    }
+
+    #[test]
+    fn parse_cat_dictionary() {
+        let rtf = "{\\rtf1\\ansi{\\*\\cxrev100}\\cxdict{\\*\\cxsystem Eclipse}\r\n\
+            {\\*\\cxs KAT}cat{\\*\\cxcomment a pet}\r\n\
+            {\\*\\cxs TP-PL}{\\cxp. }\\cxfc\r\n\
+            {\\*\\cxs -G}\\cxds ing\r\n\
+            {\\*\\cxs TK-LS}\\cxds\r\n\
+            {\\*\\cxs KR\\'e9}cr\\u233?me\r\n\
+            }";
+        let entries: Vec<(String, String)> = parse(rtf).into_iter().map(|e| (e.chord, e.translation)).collect();
+        assert_eq!(
+            entries,
+            vec![
+                ("KAT".into(), "cat".into()),
+                ("TP-PL".into(), "{.}{-|}".into()),
+                ("-G".into(), "{^ing}".into()),
+                ("TK-LS".into(), "{^}".into()),
+                ("KR\u{e9}".into(), "cr\u{e9}me".into()),
+            ]
+        );
+    }
+}
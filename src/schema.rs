@@ -5,3 +5,25 @@ table! {
         translation -> Text,
     }
 }
+
+table! {
+    dictionary_search (rowid) {
+        rowid -> Integer,
+        chord -> Text,
+        translation -> Text,
+    }
+}
+
+table! {
+    strokes (id) {
+        id -> Integer,
+        session -> Text,
+        timestamp -> BigInt,
+        raw -> Text,
+        chord -> Text,
+        translation -> Nullable<Text>,
+        undone -> Bool,
+    }
+}
+
+allow_tables_to_appear_in_same_query!(dictionary, dictionary_search, strokes);
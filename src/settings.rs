@@ -0,0 +1,288 @@
+use std::convert::TryFrom;
+use std::env;
+use std::fs;
+use std::ops::RangeInclusive;
+use std::path::PathBuf;
+use toml::value::{Table, Value};
+
+/// Settings from `~/.config/wayplover/config.toml`, every one optional so
+/// command line flags and defaults can fill in the rest:
+///
+/// ```toml
+/// protocol = "gemini"
+/// port = "/dev/ttyACM0"
+/// baud = 9600
+/// data_bits = 8
+/// parity = "none"
+/// stop_bits = 1
+/// flow_control = "none"
+/// dictionaries = ["~/steno/user.db", "~/steno/main.json"]
+/// output = "uinput"
+/// layout = "us"
+/// key_delay = 1
+/// sound = false
+/// tick_rate = 5
+///
+/// [ui]
+/// refresh = 100
+/// panes = ["output", "steno", "history", "lookup", "dictionaries"]
+///
+/// [profiles.laptop]
+/// port = "/dev/ttyUSB0"
+/// ```
+///
+/// A profile's keys replace the top level ones.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Settings {
+    pub protocol: Option<String>,
+    pub port: Option<String>,
+    pub baud: Option<u32>,
+    pub data_bits: Option<u8>,
+    pub parity: Option<String>,
+    pub stop_bits: Option<u8>,
+    pub flow_control: Option<String>,
+    pub dictionaries: Vec<String>,
+    pub output: Option<String>,
+    pub layout: Option<String>,
+    /// Milliseconds between uinput key events.
+    pub key_delay: Option<u64>,
+    pub sound: Option<bool>,
+    /// Milliseconds the workers wait between polls.
+    pub tick_rate: Option<u64>,
+    /// Milliseconds between TUI frames.
+    pub refresh: Option<u64>,
+    pub panes: Option<Vec<String>>,
+}
+impl Settings {
+    /// Where the settings live, under `$XDG_CONFIG_HOME` or `~/.config`.
+    pub fn path() -> Option<PathBuf> {
+        let base = match env::var_os("XDG_CONFIG_HOME") {
+            Some(dir) if !dir.is_empty() => PathBuf::from(dir),
+            _ => PathBuf::from(env::var_os("HOME")?).join(".config"),
+        };
+        Some(base.join("wayplover").join("config.toml"))
+    }
+
+    /// Read `path`, or the default file when there is one, with `profile`
+    /// laid over the top level.
+    pub fn load(path: Option<&str>, profile: Option<&str>) -> Result<Self, String> {
+        let (path, contents) = match path {
+            Some(path) => (PathBuf::from(path), fs::read_to_string(path).map_err(|e| format!("{}: {}", path, e))?),
+            None => match Self::path().filter(|p| p.exists()) {
+                Some(path) => {
+                    let contents = fs::read_to_string(&path).map_err(|e| format!("{}: {}", path.display(), e))?;
+                    (path, contents)
+                }
+                None if profile.is_some() => return Err("no config file for --profile".to_string()),
+                None => return Ok(Self::default()),
+            },
+        };
+        Self::parse(&contents, profile).map_err(|e| format!("{}: {}", path.display(), e))
+    }
+
+    /// Read settings from TOML, with `profile` laid over the top level.
+    fn parse(contents: &str, profile: Option<&str>) -> Result<Self, String> {
+        let mut table: Table = toml::from_str(contents).map_err(|e| e.to_string())?;
+        let profiles = table.remove("profiles");
+        if let Some(name) = profile {
+            let overlay = profiles
+                .as_ref()
+                .and_then(|p| p.get(name))
+                .and_then(Value::as_table)
+                .ok_or_else(|| format!("no profile {}", name))?;
+            for (key, value) in overlay {
+                match (table.get_mut(key), value) {
+                    (Some(Value::Table(base)), Value::Table(over)) => {
+                        base.extend(over.clone());
+                    }
+                    _ => {
+                        table.insert(key.clone(), value.clone());
+                    }
+                }
+            }
+        }
+        Self::from_table(&table)
+    }
+
+    /// These settings with every one `over` has replacing them, as command
+    /// line flags replace the config file.
+    pub fn overlay(self, over: Self) -> Self {
+        Self {
+            protocol: over.protocol.or(self.protocol),
+            port: over.port.or(self.port),
+            baud: over.baud.or(self.baud),
+            data_bits: over.data_bits.or(self.data_bits),
+            parity: over.parity.or(self.parity),
+            stop_bits: over.stop_bits.or(self.stop_bits),
+            flow_control: over.flow_control.or(self.flow_control),
+            dictionaries: if over.dictionaries.is_empty() {
+                self.dictionaries
+            } else {
+                over.dictionaries
+            },
+            output: over.output.or(self.output),
+            layout: over.layout.or(self.layout),
+            key_delay: over.key_delay.or(self.key_delay),
+            sound: over.sound.or(self.sound),
+            tick_rate: over.tick_rate.or(self.tick_rate),
+            refresh: over.refresh.or(self.refresh),
+            panes: over.panes.or(self.panes),
+        }
+    }
+
+    fn from_table(table: &Table) -> Result<Self, String> {
+        let mut settings = Self::default();
+        for (key, value) in table {
+            match key.as_str() {
+                "protocol" => settings.protocol = Some(string(key, value)?),
+                "port" => settings.port = Some(expand(&string(key, value)?)),
+                "baud" => settings.baud = Some(bounded(key, value, 1..=u32::MAX.into())?),
+                "data_bits" => settings.data_bits = Some(bounded(key, value, 5..=8)?),
+                "parity" => settings.parity = Some(string(key, value)?),
+                "stop_bits" => settings.stop_bits = Some(bounded(key, value, 1..=2)?),
+                "flow_control" => settings.flow_control = Some(string(key, value)?),
+                "dictionaries" => {
+                    settings.dictionaries = strings(key, value)?.iter().map(|p| expand(p)).collect();
+                }
+                "output" => settings.output = Some(string(key, value)?),
+                "layout" => settings.layout = Some(string(key, value)?),
+                "key_delay" => settings.key_delay = Some(integer(key, value)?),
+                "sound" => {
+                    settings.sound = Some(value.as_bool().ok_or_else(|| format!("{} should be true or false", key))?);
+                }
+                "tick_rate" => settings.tick_rate = Some(integer(key, value)?),
+                "ui" => {
+                    let ui = value.as_table().ok_or_else(|| "ui should be a table".to_string())?;
+                    for (key, value) in ui {
+                        match key.as_str() {
+                            "refresh" => settings.refresh = Some(integer(key, value)?),
+                            "panes" => settings.panes = Some(strings(key, value)?),
+                            _ => return Err(format!("unknown setting ui.{}", key)),
+                        }
+                    }
+                }
+                _ => return Err(format!("unknown setting {}", key)),
+            }
+        }
+        Ok(settings)
+    }
+}
+
+fn string(key: &str, value: &Value) -> Result<String, String> {
+    value
+        .as_str()
+        .map(String::from)
+        .ok_or_else(|| format!("{} should be a string", key))
+}
+
+fn integer(key: &str, value: &Value) -> Result<u64, String> {
+    value
+        .as_integer()
+        .filter(|&n| n >= 0)
+        .map(|n| n as u64)
+        .ok_or_else(|| format!("{} should be a positive number", key))
+}
+
+/// An integer within `range`, which `T` holds.
+fn bounded<T: TryFrom<i64>>(key: &str, value: &Value, range: RangeInclusive<i64>) -> Result<T, String> {
+    value
+        .as_integer()
+        .filter(|n| range.contains(n))
+        .and_then(|n| T::try_from(n).ok())
+        .ok_or_else(|| format!("{} should be between {} and {}", key, range.start(), range.end()))
+}
+
+fn strings(key: &str, value: &Value) -> Result<Vec<String>, String> {
+    value
+        .as_array()
+        .and_then(|values| values.iter().map(|v| v.as_str().map(String::from)).collect())
+        .ok_or_else(|| format!("{} should be a list of strings", key))
+}
+
+/// Expand a leading `~/` to the home directory.
+fn expand(path: &str) -> String {
+    match (path.strip_prefix("~/"), env::var("HOME")) {
+        (Some(rest), Ok(home)) => format!("{}/{}", home, rest),
+        _ => path.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use test_case::test_case;
+
+    const CONFIG: &str = r#"
+port = "/dev/ttyACM0"
+baud = 9600
+layout = "us"
+
+[ui]
+refresh = 100
+panes = ["output", "steno"]
+
+[profiles.laptop]
+port = "/dev/ttyUSB0"
+baud = 115200
+
+[profiles.laptop.ui]
+refresh = 50
+"#;
+
+    #[test]
+    fn top_level() {
+        let settings = Settings::parse(CONFIG, None).unwrap();
+        assert_eq!(settings.port.as_deref(), Some("/dev/ttyACM0"));
+        assert_eq!(settings.baud, Some(9600));
+        assert_eq!(settings.refresh, Some(100));
+        assert_eq!(settings.panes, Some(vec!["output".into(), "steno".into()]));
+        assert_eq!(settings.data_bits, None);
+        let line = Settings::parse("data_bits = 7\nstop_bits = 2\nparity = \"even\"", None).unwrap();
+        assert_eq!((line.data_bits, line.stop_bits, line.parity.as_deref()), (Some(7), Some(2), Some("even")));
+    }
+
+    #[test]
+    fn profile_overlay() {
+        let settings = Settings::parse(CONFIG, Some("laptop")).unwrap();
+        assert_eq!(settings.port.as_deref(), Some("/dev/ttyUSB0"));
+        assert_eq!(settings.baud, Some(115200));
+        assert_eq!(settings.layout.as_deref(), Some("us"));
+        // tables merge key by key
+        assert_eq!(settings.refresh, Some(50));
+        assert_eq!(settings.panes, Some(vec!["output".into(), "steno".into()]));
+        assert_eq!(Settings::parse(CONFIG, Some("desk")), Err("no profile desk".to_string()));
+    }
+
+    #[test]
+    fn flags_over_file_over_default() {
+        let file = Settings::parse(CONFIG, None).unwrap();
+        let flags = Settings {
+            port: Some("/dev/ttyS0".into()),
+            dictionaries: vec!["user.db".into()],
+            ..Settings::default()
+        };
+        let settings = file.overlay(flags);
+        assert_eq!(settings.port.as_deref(), Some("/dev/ttyS0"));
+        assert_eq!(settings.baud, Some(9600));
+        assert_eq!(settings.dictionaries, ["user.db"]);
+        assert_eq!(settings.key_delay.unwrap_or(1), 1);
+        let settings = Settings::parse(CONFIG, None).unwrap().overlay(Settings::default());
+        assert_eq!(settings, Settings::parse(CONFIG, None).unwrap());
+    }
+
+    #[test_case("baud = -1", "baud should be between 1 and 4294967295" ; "negative")]
+    #[test_case("baud = 4294967296", "baud should be between 1 and 4294967295" ; "too big")]
+    #[test_case("data_bits = 9", "data_bits should be between 5 and 8" ; "data bits past 8")]
+    #[test_case("data_bits = 264", "data_bits should be between 5 and 8" ; "data bits past u8")]
+    #[test_case("stop_bits = 0", "stop_bits should be between 1 and 2" ; "no stop bits")]
+    #[test_case("stop_bits = 257", "stop_bits should be between 1 and 2" ; "stop bits past u8")]
+    #[test_case("key_delay = -1", "key_delay should be a positive number" ; "negative delay")]
+    #[test_case("port = 1", "port should be a string" ; "wrong type")]
+    #[test_case("sound = \"yes\"", "sound should be true or false" ; "not a bool")]
+    #[test_case("dictionaries = [1]", "dictionaries should be a list of strings" ; "not strings")]
+    #[test_case("colour = true", "unknown setting colour" ; "unknown")]
+    #[test_case("[ui]\ntheme = 1", "unknown setting ui.theme" ; "unknown ui")]
+    fn errors(config: &str, error: &str) {
+        assert_eq!(Settings::parse(config, None), Err(error.to_string()));
+    }
+}
@@ -0,0 +1,74 @@
+use std::fs;
+use std::io::{self, ErrorKind};
+use std::os::unix::fs::FileTypeExt;
+use std::os::unix::net::{UnixListener, UnixStream};
+
+fn is_socket(path: &str) -> io::Result<bool> {
+    fs::symlink_metadata(path).map(|meta| meta.file_type().is_socket())
+}
+
+/// Listen on a non-blocking Unix socket at `path`. A socket left behind by
+/// an earlier run is replaced, but one another process still listens on, or
+/// a path that isn't a socket at all, is an error rather than removed.
+pub fn listen(path: &str) -> io::Result<UnixListener> {
+    match is_socket(path) {
+        Ok(false) => return Err(io::Error::new(ErrorKind::AlreadyExists, "exists and is not a socket")),
+        Ok(true) if UnixStream::connect(path).is_ok() => {
+            return Err(io::Error::new(ErrorKind::AddrInUse, "another process is listening on it"));
+        }
+        Ok(true) => fs::remove_file(path)?,
+        Err(e) if e.kind() == ErrorKind::NotFound => {}
+        Err(e) => return Err(e),
+    }
+    let listener = UnixListener::bind(path)?;
+    listener.set_nonblocking(true)?;
+    Ok(listener)
+}
+
+/// Remove the socket at `path` on shutdown, leaving it be if something
+/// other than a socket took its place.
+pub fn remove(path: &str) {
+    if let Ok(true) = is_socket(path) {
+        let _ = fs::remove_file(path);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+
+    fn path(name: &str) -> String {
+        let path = env::temp_dir().join(format!("wayplover-{}-{}.sock", name, std::process::id()));
+        path.to_str().unwrap().to_string()
+    }
+
+    #[test]
+    fn replaces_a_stale_socket() {
+        let path = path("stale");
+        drop(UnixListener::bind(&path).unwrap());
+        let listener = listen(&path).unwrap();
+        assert!(UnixStream::connect(&path).is_ok());
+        drop(listener);
+        remove(&path);
+        assert!(fs::symlink_metadata(&path).is_err());
+    }
+
+    #[test]
+    fn leaves_a_live_socket() {
+        let path = path("live");
+        let _live = listen(&path).unwrap();
+        assert_eq!(listen(&path).unwrap_err().kind(), ErrorKind::AddrInUse);
+        remove(&path);
+    }
+
+    #[test]
+    fn leaves_other_files() {
+        let path = path("file");
+        fs::write(&path, "notes").unwrap();
+        assert_eq!(listen(&path).unwrap_err().kind(), ErrorKind::AlreadyExists);
+        remove(&path);
+        assert_eq!(fs::read_to_string(&path).unwrap(), "notes");
+        fs::remove_file(&path).unwrap();
+    }
+}
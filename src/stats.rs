@@ -0,0 +1,191 @@
+use crate::steno::{ActionSymbol, Command};
+use crate::{info, warn};
+use json::{object, JsonValue};
+use std::collections::VecDeque;
+use std::fs::OpenOptions;
+use std::io::Write;
+use std::time::{Duration, Instant};
+
+/// How far back the rolling figures look.
+const WINDOW: Duration = Duration::from_secs(60);
+/// Speeds read zero until the session is this old, a stroke or two over a
+/// moment making no sense as words per minute.
+const SETTLE: Duration = Duration::from_secs(5);
+
+/// How fast and how cleanly this session is writing. A word is five
+/// characters, counted net of what was erased.
+#[derive(Debug, Default)]
+pub struct Stats {
+    /// Where `finish` appends the session's figures as a line of JSON.
+    pub export: Option<String>,
+    first: Option<Instant>,
+    strokes: usize,
+    errors: usize,
+    undos: usize,
+    chars: i64,
+    /// Each stroke in the last `WINDOW` and the characters it wrote.
+    recent: VecDeque<(Instant, i64)>,
+}
+impl Stats {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Count a stroke, `erase` and `typed` being what the formatter made of it.
+    pub fn record(&mut self, command: &Command, erase: &ActionSymbol, typed: usize) {
+        let now = Instant::now();
+        self.first.get_or_insert(now);
+        self.strokes += 1;
+        match command {
+            Command::Error(_) => self.errors += 1,
+            Command::Undo(_) => self.undos += 1,
+            _ => {}
+        }
+        let chars = typed as i64 - erase.erased() as i64;
+        self.chars += chars;
+        self.recent.push_back((now, chars));
+        while self.recent.front().is_some_and(|(t, _)| now.duration_since(*t) > WINDOW) {
+            self.recent.pop_front();
+        }
+    }
+
+    fn session_minutes(&self) -> f64 {
+        self.first.map_or(0.0, |first| first.elapsed().as_secs_f64() / 60.0)
+    }
+
+    /// The last `WINDOW` of writing, or less early in the session.
+    fn rolling(&self) -> (f64, usize, i64) {
+        let recent: Vec<&(Instant, i64)> = self.recent.iter().filter(|(t, _)| t.elapsed() <= WINDOW).collect();
+        let minutes = self.session_minutes().min(WINDOW.as_secs_f64() / 60.0);
+        (minutes, recent.len(), recent.iter().map(|(_, c)| c).sum())
+    }
+
+    fn rate(count: f64, minutes: f64) -> f64 {
+        if minutes > 0.0 {
+            count / minutes
+        } else {
+            0.0
+        }
+    }
+
+    /// `count` per minute, once the session has run for `SETTLE`.
+    fn speed(&self, count: f64, minutes: f64) -> f64 {
+        if self.session_minutes() < SETTLE.as_secs_f64() / 60.0 {
+            return 0.0;
+        }
+        Self::rate(count, minutes)
+    }
+
+    pub fn wpm(&self) -> f64 {
+        self.speed(self.chars as f64 / 5.0, self.session_minutes())
+    }
+
+    pub fn rolling_wpm(&self) -> f64 {
+        let (minutes, _, chars) = self.rolling();
+        self.speed(chars as f64 / 5.0, minutes)
+    }
+
+    pub fn strokes_per_second(&self) -> f64 {
+        self.speed(self.strokes as f64, self.session_minutes()) / 60.0
+    }
+
+    pub fn rolling_strokes_per_second(&self) -> f64 {
+        let (minutes, strokes, _) = self.rolling();
+        self.speed(strokes as f64, minutes) / 60.0
+    }
+
+    /// Untranslated strokes, as a percentage of all strokes.
+    pub fn error_rate(&self) -> f64 {
+        Self::rate(self.errors as f64 * 100.0, self.strokes as f64)
+    }
+
+    /// Undo strokes, as a percentage of all strokes.
+    pub fn undo_rate(&self) -> f64 {
+        Self::rate(self.undos as f64 * 100.0, self.strokes as f64)
+    }
+
+    pub fn to_json(&self) -> JsonValue {
+        object! {
+            "strokes" => self.strokes,
+            "minutes" => self.session_minutes(),
+            "wpm" => self.wpm(),
+            "rolling_wpm" => self.rolling_wpm(),
+            "strokes_per_second" => self.strokes_per_second(),
+            "rolling_strokes_per_second" => self.rolling_strokes_per_second(),
+            "error_rate" => self.error_rate(),
+            "undo_rate" => self.undo_rate(),
+        }
+    }
+
+    /// Log the session's figures and append them to `export` when set.
+    pub fn finish(&self, session: &str) {
+        let mut figures = self.to_json();
+        figures["session"] = session.into();
+        info!("Session stats {}", figures.dump());
+        let path = match &self.export {
+            Some(path) => path,
+            None => return,
+        };
+        let written = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .and_then(|mut file| writeln!(file, "{}", figures.dump()));
+        if let Err(e) = written {
+            warn!("{}: {}", path, e);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::format::{Emit, Formatter};
+
+    fn stats(strokes: usize, typed: usize) -> Stats {
+        let mut stats = Stats::new();
+        for _ in 0..strokes {
+            stats.record(&Command::Output(String::new()), &ActionSymbol::Noop, typed);
+        }
+        stats
+    }
+
+    #[test]
+    fn speeds_wait_for_the_session_to_settle() {
+        let stats = stats(2, 10);
+        assert_eq!(stats.wpm(), 0.0);
+        assert_eq!(stats.rolling_wpm(), 0.0);
+        assert_eq!(stats.strokes_per_second(), 0.0);
+        assert_eq!(stats.rolling_strokes_per_second(), 0.0);
+    }
+
+    #[test]
+    fn speeds_over_the_session() {
+        let mut stats = stats(30, 10);
+        stats.first = Instant::now().checked_sub(Duration::from_secs(30));
+        // 300 characters, 60 words, in half a minute
+        assert!((stats.wpm() - 120.0).abs() < 1.0, "{}", stats.wpm());
+        assert!((stats.rolling_wpm() - 120.0).abs() < 1.0, "{}", stats.rolling_wpm());
+        assert!((stats.strokes_per_second() - 1.0).abs() < 0.01);
+    }
+
+    #[test]
+    fn erased_characters_and_error_rates() {
+        let mut stats = Stats::new();
+        let mut formatter = Formatter::new();
+        let commands = [
+            Command::Output("cat".into()),
+            Command::Error("KAT".into()),
+            Command::Undo(Vec::new()),
+            Command::Output("{^s}".into()),
+        ];
+        for command in &commands {
+            let (erase, emits) = formatter.apply(command);
+            stats.record(command, &erase, Emit::text(&emits).chars().count());
+        }
+        // " cat", " KAT" taken back, then "s"
+        assert_eq!(stats.chars, 5);
+        assert_eq!(stats.error_rate(), 25.0);
+        assert_eq!(stats.undo_rate(), 25.0);
+    }
+}
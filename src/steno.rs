@@ -1,29 +1,36 @@
+use crate::import::Conflict;
 use crate::*;
+use diesel::connection::SimpleConnection;
 use diesel::prelude::*;
-use regex::*;
-use std::borrow::*;
 #[derive(Debug)]
 pub struct Chord(Vec<String>);
-#[derive(Clone)]
+#[derive(Clone, Debug, PartialEq)]
 pub enum Command {
-    Append(String),
     Output(String),
+    Replace(usize, String),
     Error(String),
-    Delete,
+    Undo(Vec<String>),
 }
 
 pub enum ActionSymbol {
-    Suffix,
-    Glue,
-    Delete,
+    Replace(usize),
     Noop,
 }
+impl ActionSymbol {
+    /// How many characters go before the new text is typed.
+    pub fn erased(&self) -> usize {
+        match self {
+            Self::Replace(n) => *n,
+            Self::Noop => 0,
+        }
+    }
+}
 impl Command {
     pub fn as_text(&self) -> (ActionSymbol, String) {
         match self {
-            Self::Append(s) => (ActionSymbol::Suffix, s.to_owned()),
             Self::Output(s) => (ActionSymbol::Noop, s.to_owned()),
-            Self::Delete => (ActionSymbol::Delete, String::new()),
+            Self::Replace(n, s) => (ActionSymbol::Replace(*n), s.to_owned()),
+            Self::Undo(s) => (ActionSymbol::Replace(1), s.concat()),
             Self::Error(s) => (ActionSymbol::Noop, s.to_owned()),
         }
     }
@@ -39,37 +46,38 @@ impl Chord {
         steno_vec.sort_by(|k1, k2| STENO_ORDER.compare(k1.as_str(), k2.as_str()));
 
         if steno_vec.contains(&"#".to_string()) {
-            let mut number_steno_vec = Vec::new();
-            for (i, l) in steno_vec.iter_mut().enumerate() {
-                if let Some(&number) = STENO_NUMBERS.get(l.as_str()) {
-                    number_steno_vec[i] = number.to_string();
-                }
+            let number_steno_vec: Vec<String> = steno_vec
+                .iter()
+                .map(|l| match STENO_NUMBERS.get(l.as_str()) {
+                    Some(&number) => number.to_string(),
+                    None => l.to_owned(),
+                })
+                .collect();
+            // the number bar is implied once any key became a digit
+            if number_steno_vec != steno_vec {
+                return Self(number_steno_vec.into_iter().filter(|l| l != "#").collect());
             }
-            return Self(number_steno_vec);
         }
-        //let steno_vec = steno_vec.iter_mut().map(|e| e.replace("-", "")).collect();
         Self(steno_vec)
     }
     pub fn resolve(&self, dict: &mut Dictionary) -> Command {
-        dict.lookup(
-            self.0
-                .iter()
-                .map(|e| {
-                    let mut e = Vec::from(e.split("").collect::<Vec<&str>>());
-                    if e[0] == "-" {
-                        for i in 1..e.len() {
-                            if e[i] == "-" {
-                                e[i] = "";
-                            }
-                        }
-                        e.join("")
-                    } else {
-                        e.join("").replace("-", "")
-                    }
-                })
-                .collect::<Vec<String>>()
-                .join(""),
-        )
+        dict.lookup(self.steno())
+    }
+    /// The stroke in dictionary notation, e.g. `S-T`, `STA` or `-PB`. A hyphen
+    /// separates the banks when no vowel or star does.
+    pub fn steno(&self) -> String {
+        let middle = ["A-", "O-", "5-", "0-", "*", "-E", "-U"];
+        let needs_hyphen = !self.0.iter().any(|k| middle.contains(&k.as_str()));
+        let mut steno = String::new();
+        let mut hyphenated = false;
+        for key in &self.0 {
+            if key.starts_with('-') && needs_hyphen && !hyphenated {
+                steno.push('-');
+                hyphenated = true;
+            }
+            steno.push_str(&key.replace("-", ""));
+        }
+        steno
     }
     pub fn plain(&self) -> String {
         self.0
@@ -86,70 +94,696 @@ impl Chord {
     }
 }
 use sm::sm;
-use sm::NoneEvent;
 sm! {
     Translator {
-        InitialStates { Idle, Waiting, Finished}
+        InitialStates { Idle, Waiting, Finished }
         NeedMore {
             Idle => Waiting
-                Waiting => Waiting
+            Waiting => Waiting
+            Finished => Waiting
         }
         Finish {
+            Idle => Finished
             Waiting => Finished
+            Finished => Finished
         }
     }
 }
-use Translator::*;
-pub struct Dictionary {
-    conn: SqliteConnection,
+impl Translator::Variant {
+    /// Advance the translator after a stroke, `more` being whether the buffered
+    /// strokes are still the start of a longer outline.
+    fn advance(self, more: bool) -> Self {
+        use Translator::Variant::*;
+        use Translator::*;
+        match (self, more) {
+            (InitialIdle(m), true) => m.transition(NeedMore).as_enum(),
+            (InitialIdle(m), false) => m.transition(Finish).as_enum(),
+            (InitialWaiting(m), true) => m.transition(NeedMore).as_enum(),
+            (InitialWaiting(m), false) => m.transition(Finish).as_enum(),
+            (InitialFinished(m), true) => m.transition(NeedMore).as_enum(),
+            (InitialFinished(m), false) => m.transition(Finish).as_enum(),
+            (WaitingByNeedMore(m), true) => m.transition(NeedMore).as_enum(),
+            (WaitingByNeedMore(m), false) => m.transition(Finish).as_enum(),
+            (FinishedByFinish(m), true) => m.transition(NeedMore).as_enum(),
+            (FinishedByFinish(m), false) => m.transition(Finish).as_enum(),
+        }
+    }
+    fn is_waiting(&self) -> bool {
+        use Translator::Variant::*;
+        matches!(self, InitialWaiting(_) | WaitingByNeedMore(_))
+    }
+}
+
+/// A run of strokes and the dictionary text they resolved to, `None` when the
+/// strokes are untranslated. `replaced` holds the translations it took over
+/// so undoing it can bring them back.
+#[derive(Clone, Debug)]
+pub struct Translation {
+    pub strokes: Vec<String>,
+    pub text: Option<String>,
+    pub replaced: Vec<Translation>,
+}
+impl Translation {
+    pub fn outline(&self) -> String {
+        self.strokes.join("/")
+    }
+    /// What the translation writes, the raw outline when untranslated.
+    pub fn as_text(&self) -> String {
+        self.text.clone().unwrap_or_else(|| self.outline())
+    }
+}
+
+/// Another way to write what the last few translations wrote.
+#[derive(Clone, Debug)]
+pub struct Suggestion {
+    pub text: String,
+    /// The outline it was written with.
+    pub used: String,
+    /// Outlines that write `text` in no more strokes, fewest first.
+    pub outlines: Vec<String>,
+}
+
+/// Translations kept for undo, shared with the formatter's records.
+pub const UNDO_LIMIT: usize = 1000;
+/// How far back an undo looks for translations that may still be extended.
+const LOOKBACK: usize = 8;
+
+/// Trigram index over the dictionary for the lookup pane, kept in step with
+/// it by triggers. REPLACE only fires the delete trigger with
+/// `recursive_triggers` on, which every connection sets. Not a migration:
+/// trigram tokenizing needs SQLite 3.34 built with FTS5, and without it
+/// lookups scan the table instead.
+const SEARCH_INDEX: &str = "
+CREATE VIRTUAL TABLE dictionary_search USING fts5(
+    chord,
+    translation,
+    content = 'dictionary',
+    content_rowid = 'id',
+    tokenize = 'trigram'
+);
+INSERT INTO dictionary_search(dictionary_search) VALUES ('rebuild');
+CREATE TRIGGER dictionary_search_insert AFTER INSERT ON dictionary BEGIN
+    INSERT INTO dictionary_search(rowid, chord, translation) VALUES (new.id, new.chord, new.translation);
+END;
+CREATE TRIGGER dictionary_search_delete AFTER DELETE ON dictionary BEGIN
+    INSERT INTO dictionary_search(dictionary_search, rowid, chord, translation)
+        VALUES ('delete', old.id, old.chord, old.translation);
+END;
+CREATE TRIGGER dictionary_search_update AFTER UPDATE ON dictionary BEGIN
+    INSERT INTO dictionary_search(dictionary_search, rowid, chord, translation)
+        VALUES ('delete', old.id, old.chord, old.translation);
+    INSERT INTO dictionary_search(rowid, chord, translation) VALUES (new.id, new.chord, new.translation);
+END;
+";
+
+/// One database in the dictionary stack.
+pub struct Source {
+    pub name: String,
+    pub enabled: bool,
     path: String,
-    last: Vec<String>,
+    conn: SqliteConnection,
 }
-impl Clone for Dictionary {
+impl Clone for Source {
     fn clone(&self) -> Self {
-        let conn = SqliteConnection::establish(self.path.as_str()).unwrap();
-        Self{conn, last: self.last.clone(), path: self.path.clone()}
+        Self {
+            conn: Self::connect(&self.path),
+            name: self.name.clone(),
+            enabled: self.enabled,
+            path: self.path.clone(),
+        }
     }
 }
-impl Dictionary {
-    pub fn from_file(path: &str) -> Self {
-        let conn = SqliteConnection::establish(path).unwrap();
+impl Source {
+    pub fn open(path: &str) -> Self {
+        let conn = Self::connect(path);
+        if let Err(e) = embedded_migrations::run(&conn) {
+            warn!("Migrating {}: {}", path, e);
+        }
+        Self::index(&conn, path);
+        let name = std::path::Path::new(path)
+            .file_stem()
+            .map_or(path.to_string(), |stem| stem.to_string_lossy().to_string());
         Self {
-            conn,
+            name,
+            enabled: true,
             path: path.to_string(),
-            last: Vec::new(),
+            conn,
         }
     }
 
-    fn lookup(&mut self, in_chord: String) -> Command {
-        if in_chord.eq(&"*".to_string()) {
-            return Command::Delete;
+    fn connect(path: &str) -> SqliteConnection {
+        let conn = SqliteConnection::establish(path).unwrap();
+        // so replacing an entry also takes it out of dictionary_search
+        if let Err(e) = conn.batch_execute("PRAGMA recursive_triggers = ON") {
+            warn!("{}: {}", path, e);
         }
-        self.last.push(in_chord.clone());
+        conn
+    }
+
+    /// Add `SEARCH_INDEX` unless it is there already or SQLite can't build it.
+    fn index(conn: &SqliteConnection, path: &str) {
+        use crate::schema::dictionary_search::dsl::*;
+        if dictionary_search.select(rowid).first::<i32>(conn).optional().is_ok() {
+            return;
+        }
+        if let Err(e) = conn.transaction(|| conn.batch_execute(SEARCH_INDEX)) {
+            warn!("{}: no search index, lookups scan the dictionary: {}", path, e);
+        }
+    }
+
+    fn entry(&self, outline: &str) -> Option<String> {
         use crate::schema::dictionary::dsl::*;
-        let entry = dictionary
-            .filter(chord.eq(&in_chord))
+        dictionary
+            .filter(chord.eq(outline))
             .order_by(id.desc())
-            .first::<models::Entry>(&self.conn);
+            .first::<models::Entry>(&self.conn)
+            .ok()
+            .map(|e| e.translation)
+    }
 
-        match entry {
-            Ok(e) => {
-                info!("Chord: {}, ({}, {})", in_chord, e.chord, e.translation);
-                self.last.clear();
-                return Command::Output(e.translation);
-            }
-            Err(_) => {
-                return Command::Error(in_chord);
-            }
-        }
+    /// Whether any entry continues `outline` with further strokes. Uses a range
+    /// over the chord index rather than `LIKE`, `'0'` being the byte after `'/'`.
+    fn has_prefix(&self, outline: &str) -> bool {
+        use crate::schema::dictionary::dsl::*;
+        dictionary
+            .select(id)
+            .filter(chord.gt(format!("{}/", outline)))
+            .filter(chord.lt(format!("{}0", outline)))
+            .first::<i32>(&self.conn)
+            .is_ok()
+    }
+
+    pub fn len(&self) -> i64 {
+        use crate::schema::dictionary::dsl::*;
+        dictionary.count().get_result(&self.conn).unwrap_or(0)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Insert entries in one transaction, resolving clashes on the unique
+    /// chord column by `conflict`.
+    pub fn insert(&self, entries: &[models::NewEntry], conflict: Conflict) -> QueryResult<()> {
+        use crate::schema::dictionary::dsl::*;
+        self.conn.transaction(|| {
+            match conflict {
+                Conflict::Keep => diesel::insert_or_ignore_into(dictionary)
+                    .values(entries)
+                    .execute(&self.conn)?,
+                Conflict::Replace => diesel::replace_into(dictionary).values(entries).execute(&self.conn)?,
+            };
+            Ok(())
+        })
+    }
+
+    /// Run `f` in one transaction, rolled back when it fails.
+    pub fn transaction<T, E, F>(&self, f: F) -> Result<T, E>
+    where
+        F: FnOnce() -> Result<T, E>,
+        E: From<diesel::result::Error>,
+    {
+        self.conn.transaction(f)
+    }
+
+    pub fn entries(&self) -> Vec<models::Entry> {
+        use crate::schema::dictionary::dsl::*;
+        dictionary
+            .order_by(id.asc())
+            .load::<models::Entry>(&self.conn)
+            .unwrap()
+    }
+
+    /// Entries whose chord contains `search`. Goes through the
+    /// `dictionary_search` trigram index, or scans the table when SQLite was
+    /// built without FTS5.
+    pub fn find(&self, search: &str) -> Vec<models::Entry> {
+        use crate::schema::dictionary::dsl::*;
+        use crate::schema::dictionary_search::dsl as fts;
+        let pattern = format!("%{}%", search);
+        let indexed = fts::dictionary_search
+            .select(fts::rowid)
+            .filter(fts::chord.like(&pattern));
+        dictionary
+            .filter(id.eq_any(indexed))
+            .order_by(id.asc())
+            .load::<models::Entry>(&self.conn)
+            .or_else(|_| {
+                dictionary
+                    .filter(chord.like(&pattern))
+                    .order_by(id.asc())
+                    .load::<models::Entry>(&self.conn)
+            })
+            .unwrap()
     }
 
-    pub fn find(&self, search: &String) -> Vec<models::Entry> {
+    /// Entries translating to exactly `text`, through the translation index.
+    pub fn outlines(&self, text: &str) -> Vec<models::Entry> {
         use crate::schema::dictionary::dsl::*;
         dictionary
-            .filter(chord.like(format!("%{}%", search)))
+            .filter(translation.eq(text))
             .order_by(id.asc())
             .load::<models::Entry>(&self.conn)
             .unwrap()
     }
+
+    /// Entries whose translation contains `search`, like `find`.
+    pub fn find_translation(&self, search: &str) -> Vec<models::Entry> {
+        use crate::schema::dictionary::dsl::*;
+        use crate::schema::dictionary_search::dsl as fts;
+        let pattern = format!("%{}%", search);
+        let indexed = fts::dictionary_search
+            .select(fts::rowid)
+            .filter(fts::translation.like(&pattern));
+        dictionary
+            .filter(id.eq_any(indexed))
+            .order_by(id.asc())
+            .load::<models::Entry>(&self.conn)
+            .or_else(|_| {
+                dictionary
+                    .filter(translation.like(&pattern))
+                    .order_by(id.asc())
+                    .load::<models::Entry>(&self.conn)
+            })
+            .unwrap()
+    }
+}
+
+/// An ordered stack of dictionaries, earlier ones taking priority, and the
+/// translator state built on top of them.
+#[derive(Clone)]
+pub struct Dictionary {
+    sources: Vec<Source>,
+    translations: Vec<Translation>,
+    pending: usize,
+    state: Translator::Variant,
+}
+impl Dictionary {
+    pub fn from_file(path: &str) -> Self {
+        Self::from_files(&[path.to_string()])
+    }
+
+    pub fn from_files(paths: &[String]) -> Self {
+        Self {
+            sources: paths.iter().map(|path| Source::open(path)).collect(),
+            translations: Vec::new(),
+            pending: 0,
+            state: Translator::Machine::new(Translator::Idle).as_enum(),
+        }
+    }
+
+    pub fn sources(&self) -> &[Source] {
+        &self.sources
+    }
+
+    /// The highest priority dictionary, where new entries are written.
+    pub fn top(&self) -> &Source {
+        &self.sources[0]
+    }
+
+    pub fn toggle(&mut self, i: usize) {
+        if let Some(source) = self.sources.get_mut(i) {
+            source.enabled = !source.enabled;
+            info!("Dictionary {} enabled: {}", source.name, source.enabled);
+        }
+    }
+
+    /// The translation the last stroke made or extended.
+    pub fn last(&self) -> Option<&Translation> {
+        self.translations.last()
+    }
+
+    fn active(&self) -> impl Iterator<Item = &Source> {
+        self.sources.iter().filter(|s| s.enabled)
+    }
+
+    fn lookup(&mut self, in_chord: String) -> Command {
+        if in_chord.eq(&"*".to_string()) {
+            return self.undo();
+        }
+        let window = self.translations.len() - self.pending;
+        // while waiting, try the longest match first: every pending
+        // translation plus this stroke, then shorter tails
+        let extend = if self.state.is_waiting() {
+            window..self.translations.len()
+        } else {
+            window..window
+        };
+        for i in extend {
+            let mut strokes: Vec<String> = self.translations[i..]
+                .iter()
+                .flat_map(|t| t.strokes.clone())
+                .collect();
+            strokes.push(in_chord.clone());
+            let outline = strokes.join("/");
+            if let Some(text) = self.entry(&outline) {
+                info!("Chord: {}, ({}, {})", in_chord, outline, text);
+                let replaced = self.translations.split_off(i);
+                let count = replaced.len();
+                let dropped = self.push(Translation {
+                    strokes,
+                    text: Some(text.clone()),
+                    replaced,
+                });
+                self.settle(window.saturating_sub(dropped));
+                return Command::Replace(count, text);
+            }
+        }
+        let text = self.entry(&in_chord);
+        let dropped = self.push(Translation {
+            strokes: vec![in_chord.clone()],
+            text: text.clone(),
+            replaced: Vec::new(),
+        });
+        self.settle(window.saturating_sub(dropped));
+        match text {
+            Some(text) => {
+                info!("Chord: {}, ({}, {})", in_chord, in_chord, text);
+                Command::Output(text)
+            }
+            None => Command::Error(in_chord),
+        }
+    }
+
+    /// Take back the last translation, restoring whatever it replaced.
+    fn undo(&mut self) -> Command {
+        let restored = match self.translations.pop() {
+            Some(last) => last.replaced,
+            None => return Command::Undo(Vec::new()),
+        };
+        info!("Undo, restoring {} translation(s)", restored.len());
+        let texts = restored.iter().map(Translation::as_text).collect();
+        self.translations.extend(restored);
+        self.settle(self.translations.len().saturating_sub(LOOKBACK));
+        Command::Undo(texts)
+    }
+
+    /// Add a translation, returning how many of the oldest were dropped to
+    /// stay within `UNDO_LIMIT`, indices before it shifting down by as many.
+    fn push(&mut self, translation: Translation) -> usize {
+        self.translations.push(translation);
+        let dropped = self.translations.len().saturating_sub(UNDO_LIMIT);
+        self.translations.drain(..dropped);
+        dropped
+    }
+
+    /// Work out which translations from `window` on can still begin a longer
+    /// outline, those being the ones the next stroke is matched against.
+    fn settle(&mut self, window: usize) {
+        let len = self.translations.len();
+        let start = (window.min(len)..len).find(|&i| {
+            let outline = self.translations[i..]
+                .iter()
+                .map(Translation::outline)
+                .collect::<Vec<String>>()
+                .join("/");
+            self.has_prefix(&outline)
+        });
+        self.pending = start.map_or(0, |i| len - i);
+        self.state = self.state.clone().advance(start.is_some());
+        debug!(
+            "Translator waiting: {}, pending: {}",
+            self.state.is_waiting(),
+            self.pending
+        );
+    }
+
+    fn entry(&self, outline: &str) -> Option<String> {
+        self.active().find_map(|s| s.entry(outline))
+    }
+
+    fn has_prefix(&self, outline: &str) -> bool {
+        self.active().any(|s| s.has_prefix(outline))
+    }
+
+    /// What each enabled dictionary translates `outline` to, tagged with its
+    /// source, the first one being the translation in effect.
+    pub fn definitions(&self, outline: &str) -> Vec<(String, String)> {
+        self.active()
+            .filter_map(|s| s.entry(outline).map(|text| (s.name.clone(), text)))
+            .collect()
+    }
+
+    /// Suggestions for the last translation and the phrases it ends, going
+    /// back up to `depth` translations. Untranslated strokes and translations
+    /// with formatting stop a phrase.
+    pub fn suggestions(&self, depth: usize) -> Vec<Suggestion> {
+        let mut suggestions = Vec::new();
+        let mut words: Vec<&str> = Vec::new();
+        let mut strokes: Vec<String> = Vec::new();
+        for translation in self.translations.iter().rev().take(depth) {
+            match &translation.text {
+                Some(text) if !text.contains('{') => words.insert(0, text),
+                _ => break,
+            }
+            strokes.insert(0, translation.outline());
+            let text = words.join(" ");
+            let used = strokes.join("/");
+            let count = used.split('/').count();
+            let mut outlines: Vec<String> = Vec::new();
+            for entry in self.outlines(&text) {
+                // an outline a higher dictionary overrides writes something else
+                if entry.chord != used
+                    && entry.chord.split('/').count() <= count
+                    && !outlines.contains(&entry.chord)
+                    && self.entry(&entry.chord).as_ref() == Some(&text)
+                {
+                    outlines.push(entry.chord);
+                }
+            }
+            if !outlines.is_empty() {
+                suggestions.push(Suggestion { text, used, outlines });
+            }
+        }
+        suggestions
+    }
+
+    /// Entries of every enabled dictionary translating to exactly `text`,
+    /// fewest strokes first.
+    pub fn outlines(&self, text: &str) -> Vec<models::Entry> {
+        let mut entries: Vec<models::Entry> = self.active().flat_map(|s| s.outlines(text)).collect();
+        entries.sort_by_key(|e| (e.chord.split('/').count(), e.chord.len()));
+        entries
+    }
+
+    /// Search every enabled dictionary, each result tagged with its source.
+    pub fn find(&self, search: &str) -> Vec<(String, models::Entry)> {
+        self.active()
+            .flat_map(|s| s.find(search).into_iter().map(move |e| (s.name.clone(), e)))
+            .collect()
+    }
+
+    /// Reverse lookup: entries whose translation contains `search`, ignoring
+    /// case. Exact matches come first, then translations starting with it,
+    /// then the rest, each fewest strokes first. Outlines a higher dictionary
+    /// overrides are left out.
+    pub fn find_translation(&self, search: &str) -> Vec<(String, models::Entry)> {
+        let active: Vec<&Source> = self.active().collect();
+        let mut results = Vec::new();
+        for (i, source) in active.iter().enumerate() {
+            for entry in source.find_translation(search) {
+                // an outline a higher dictionary has is written by that one
+                if active[..i].iter().all(|higher| higher.entry(&entry.chord).is_none()) {
+                    results.push((source.name.clone(), entry));
+                }
+            }
+        }
+        let search = search.to_lowercase();
+        results.sort_by_cached_key(|(_, e)| {
+            let translation = e.translation.to_lowercase();
+            let rank = match translation {
+                t if t == search => 0,
+                t if t.starts_with(&search) => 1,
+                _ => 2,
+            };
+            (rank, e.chord.split('/').count(), e.chord.len())
+        });
+        results
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::NewEntry;
+
+    /// A stack of in-memory dictionaries, the first taking priority.
+    fn stack(layers: &[&[(&str, &str)]]) -> Dictionary {
+        let dictionary = Dictionary::from_files(&vec![":memory:".to_string(); layers.len()]);
+        for (source, entries) in dictionary.sources.iter().zip(layers) {
+            let entries: Vec<NewEntry> = entries
+                .iter()
+                .map(|(chord, translation)| NewEntry {
+                    chord: chord.to_string(),
+                    translation: translation.to_string(),
+                })
+                .collect();
+            source.insert(&entries, Conflict::Replace).unwrap();
+        }
+        dictionary
+    }
+
+    fn dictionary(entries: &[(&str, &str)]) -> Dictionary {
+        stack(&[entries])
+    }
+
+    #[test]
+    fn fresh_database_has_every_table() {
+        use crate::schema::{dictionary, dictionary_search};
+        let source = Source::open(":memory:");
+        assert_eq!(dictionary::table.count().get_result(&source.conn), Ok(1));
+        assert_eq!(dictionary_search::table.count().get_result(&source.conn), Ok(1));
+        // opening it again leaves the index be
+        Source::index(&source.conn, ":memory:");
+        assert_eq!(source.find("SA").len(), 1);
+    }
+
+    /// Take `SEARCH_INDEX` out again, as if SQLite couldn't build it.
+    fn drop_search_index(source: &Source) {
+        source
+            .conn
+            .batch_execute(
+                "DROP TRIGGER dictionary_search_insert; DROP TRIGGER dictionary_search_delete;
+                DROP TRIGGER dictionary_search_update; DROP TABLE dictionary_search;",
+            )
+            .unwrap();
+    }
+
+    #[test]
+    fn find_without_search_index() {
+        let source = Source::open(":memory:");
+        drop_search_index(&source);
+        let entry = NewEntry {
+            chord: "KAT".into(),
+            translation: "cat".into(),
+        };
+        source.insert(&[entry], Conflict::Replace).unwrap();
+        let found: Vec<String> = source.find("A").into_iter().map(|e| e.chord).collect();
+        assert_eq!(found, ["SAP", "KAT"]);
+        assert_eq!(source.find_translation("ca").len(), 1);
+    }
+
+    #[test]
+    fn find_translation_ranks_exact_then_prefix_then_substring() {
+        let dictionary = dictionary(&[
+            ("TKOG/KAT", "dogcat"),
+            ("KAT/-L/OG", "catalog"),
+            ("SKAT", "scat"),
+            ("KATS", "cats"),
+            ("KA*T", "Cat"),
+            ("KAT", "cat"),
+            ("TKOG", "dog"),
+        ]);
+        let ranked = ["KAT", "KA*T", "KATS", "KAT/-L/OG", "SKAT", "TKOG/KAT"];
+        let found = |search: &str| -> Vec<String> {
+            dictionary.find_translation(search).into_iter().map(|(_, e)| e.chord).collect()
+        };
+        assert_eq!(found("cat"), ranked);
+        assert_eq!(found("CAT"), ranked);
+        drop_search_index(dictionary.top());
+        assert_eq!(found("cat"), ranked);
+    }
+
+    #[test]
+    fn multi_stroke_outline_replaces_provisional_translation() {
+        let mut dictionary = dictionary(&[("A", "a"), ("A/B", "ab"), ("A/B/C", "abc"), ("D", "d")]);
+        assert_eq!(dictionary.lookup("A".into()), Command::Output("a".into()));
+        assert!(dictionary.state.is_waiting());
+        assert_eq!(dictionary.lookup("B".into()), Command::Replace(1, "ab".into()));
+        assert_eq!(dictionary.lookup("C".into()), Command::Replace(1, "abc".into()));
+        assert!(!dictionary.state.is_waiting());
+        assert_eq!(dictionary.lookup("D".into()), Command::Output("d".into()));
+    }
+
+    #[test]
+    fn higher_dictionary_wins() {
+        let mut dictionary = stack(&[&[("KAT", "cat")], &[("KAT", "kit"), ("TKOG", "dog")]]);
+        assert_eq!(dictionary.lookup("KAT".into()), Command::Output("cat".into()));
+        assert_eq!(dictionary.lookup("TKOG".into()), Command::Output("dog".into()));
+        let definitions: Vec<String> = dictionary.definitions("KAT").into_iter().map(|(_, t)| t).collect();
+        assert_eq!(definitions, ["cat", "kit"]);
+    }
+
+    #[test]
+    fn disabled_dictionary_falls_through() {
+        let mut dictionary = stack(&[&[("KAT", "cat"), ("A/B", "ab")], &[("KAT", "kit")]]);
+        dictionary.toggle(0);
+        assert_eq!(dictionary.lookup("KAT".into()), Command::Output("kit".into()));
+        // nor does it hold strokes back for its longer outlines
+        assert_eq!(dictionary.lookup("A".into()), Command::Error("A".into()));
+        assert!(!dictionary.state.is_waiting());
+        dictionary.toggle(0);
+        assert_eq!(dictionary.lookup("KAT".into()), Command::Output("cat".into()));
+    }
+
+    #[test]
+    fn searches_merge_dictionaries_without_overridden_outlines() {
+        let mut dictionary = stack(&[
+            &[("KAT", "cat"), ("TKOG", "dog")],
+            &[("KAT", "cat"), ("KA*T", "cat"), ("TKOG", "cat")],
+        ]);
+        let found: Vec<String> = dictionary.find_translation("cat").into_iter().map(|(_, e)| e.chord).collect();
+        assert_eq!(found, ["KAT", "KA*T"]);
+        dictionary.lookup("KAT".into());
+        let suggestions: Vec<(String, Vec<String>)> =
+            dictionary.suggestions(1).into_iter().map(|s| (s.text, s.outlines)).collect();
+        assert_eq!(suggestions, [("cat".into(), vec!["KA*T".into()])]);
+    }
+
+    #[test]
+    fn outline_resolves_past_undo_limit() {
+        let mut dictionary = dictionary(&[("A", "a"), ("A/B", "ab"), ("A/B/C", "abc"), ("D", "d")]);
+        for _ in 0..=UNDO_LIMIT {
+            dictionary.lookup("D".into());
+        }
+        assert_eq!(dictionary.translations.len(), UNDO_LIMIT);
+        assert_eq!(dictionary.lookup("A".into()), Command::Output("a".into()));
+        assert_eq!(dictionary.lookup("B".into()), Command::Replace(1, "ab".into()));
+        assert_eq!(dictionary.lookup("C".into()), Command::Replace(1, "abc".into()));
+    }
+
+    #[test]
+    fn undo_restores_replaced_translations() {
+        let mut dictionary = dictionary(&[("A", "a"), ("A/B", "ab"), ("A/B/C", "abc")]);
+        dictionary.lookup("A".into());
+        dictionary.lookup("B".into());
+        assert_eq!(dictionary.lookup("*".into()), Command::Undo(vec!["a".into()]));
+        assert_eq!(dictionary.last().map(Translation::outline), Some("A".into()));
+        // the restored translation can still be extended
+        assert_eq!(dictionary.lookup("B".into()), Command::Replace(1, "ab".into()));
+        assert_eq!(dictionary.lookup("*".into()), Command::Undo(vec!["a".into()]));
+        assert_eq!(dictionary.lookup("*".into()), Command::Undo(Vec::new()));
+        assert!(dictionary.last().is_none());
+    }
+
+    #[test]
+    fn suggestions_for_words_and_phrases() {
+        let mut dictionary = dictionary(&[
+            ("THE", "the"),
+            ("-T", "the"),
+            ("KAT", "cat"),
+            ("TH-/KAT", "the cat"),
+            ("-T/KAT/-S", "the cat"),
+            ("KA/TH-", "catheter"),
+        ]);
+        dictionary.lookup("THE".into());
+        dictionary.lookup("KAT".into());
+        let suggestions: Vec<(String, String, Vec<String>)> = dictionary
+            .suggestions(4)
+            .into_iter()
+            .map(|s| (s.text, s.used, s.outlines))
+            .collect();
+        assert_eq!(suggestions, [("the cat".into(), "THE/KAT".into(), vec!["TH-/KAT".into()])]);
+        dictionary.lookup("-T".into());
+        assert_eq!(dictionary.suggestions(1)[0].outlines, ["THE"]);
+    }
+
+    #[test]
+    fn untranslated_stroke_ends_the_outline() {
+        let mut dictionary = dictionary(&[("A", "a"), ("A/B", "ab")]);
+        dictionary.lookup("A".into());
+        assert_eq!(dictionary.lookup("C".into()), Command::Error("C".into()));
+        assert!(!dictionary.state.is_waiting());
+        assert_eq!(dictionary.lookup("B".into()), Command::Error("B".into()));
+    }
 }
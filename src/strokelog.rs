@@ -0,0 +1,191 @@
+use crate::models::NewStroke;
+use crate::steno::{Chord, Command, UNDO_LIMIT};
+use crate::warn;
+use diesel::prelude::*;
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+embed_migrations!("migrations_strokes");
+
+/// Strokes are written in batches of this many, or sooner once the oldest
+/// has waited `FLUSH_AFTER`.
+const BATCH: usize = 64;
+const FLUSH_AFTER: Duration = Duration::from_secs(10);
+
+/// A logged stroke an undo may take back: still queued, by its place in the
+/// queue, or written, by its id.
+#[derive(Clone, Copy, Debug)]
+enum Logged {
+    Queued(usize),
+    Written(i32),
+}
+
+/// Every stroke written, for statistics and drills, kept in a database of
+/// its own rather than with the dictionaries. Strokes are queued and written
+/// in batches so logging stays off the translate path.
+pub struct StrokeLog {
+    path: String,
+    conn: SqliteConnection,
+    session: String,
+    queue: Vec<NewStroke>,
+    /// When the oldest queued stroke came in.
+    since: Option<Instant>,
+    /// Strokes an undo would take back, latest last.
+    standing: Vec<Logged>,
+}
+impl StrokeLog {
+    /// Where the log lives, under `$XDG_DATA_HOME` or `~/.local/share`.
+    pub fn path() -> Option<PathBuf> {
+        let base = match env::var_os("XDG_DATA_HOME") {
+            Some(dir) if !dir.is_empty() => PathBuf::from(dir),
+            _ => PathBuf::from(env::var_os("HOME")?).join(".local").join("share"),
+        };
+        Some(base.join("wayplover").join("strokes.db"))
+    }
+
+    /// Open the log at `path`, tagging this run's strokes with `session`.
+    pub fn open(path: &str, session: &str) -> Result<Self, String> {
+        if let Some(dir) = Path::new(path).parent().filter(|d| !d.as_os_str().is_empty()) {
+            fs::create_dir_all(dir).map_err(|e| format!("{}: {}", dir.display(), e))?;
+        }
+        let conn = SqliteConnection::establish(path).map_err(|e| format!("{}: {}", path, e))?;
+        embedded_migrations::run(&conn).map_err(|e| format!("Migrating {}: {}", path, e))?;
+        Ok(Self {
+            path: path.to_string(),
+            conn,
+            session: session.to_string(),
+            queue: Vec::new(),
+            since: None,
+            standing: Vec::new(),
+        })
+    }
+
+    /// Log a stroke and what it resolved to. Each translation takes one
+    /// stroke, so an undo takes back the latest stroke still standing.
+    pub fn record(&mut self, chord: &Chord, command: &Command) {
+        if let Command::Undo(_) = command {
+            match self.standing.pop() {
+                Some(Logged::Queued(i)) => self.queue[i].undone = true,
+                Some(Logged::Written(stroke)) => {
+                    use crate::schema::strokes::dsl::*;
+                    let updated = diesel::update(strokes.find(stroke))
+                        .set(undone.eq(true))
+                        .execute(&self.conn);
+                    if let Err(e) = updated {
+                        warn!("{}: logging undo: {}", self.path, e);
+                    }
+                }
+                None => {}
+            }
+        }
+        let translation = match command {
+            Command::Output(text) | Command::Replace(_, text) => Some(text.clone()),
+            Command::Error(_) | Command::Undo(_) => None,
+        };
+        self.queue.push(NewStroke {
+            session: self.session.clone(),
+            timestamp: chrono::Utc::now().timestamp_millis(),
+            raw: chord.raw().join(" "),
+            chord: chord.steno(),
+            translation,
+            undone: false,
+        });
+        if !matches!(command, Command::Undo(_)) {
+            self.standing.push(Logged::Queued(self.queue.len() - 1));
+            let dropped = self.standing.len().saturating_sub(UNDO_LIMIT);
+            self.standing.drain(..dropped);
+        }
+        let since = *self.since.get_or_insert_with(Instant::now);
+        if self.queue.len() >= BATCH || since.elapsed() >= FLUSH_AFTER {
+            self.flush();
+        }
+    }
+
+    /// Write the queued strokes in one transaction.
+    pub fn flush(&mut self) {
+        if self.queue.is_empty() {
+            return;
+        }
+        use crate::schema::strokes::dsl::*;
+        let written = self.conn.transaction(|| {
+            diesel::insert_into(strokes).values(&self.queue).execute(&self.conn)?;
+            strokes.select(id).order_by(id.desc()).first::<i32>(&self.conn)
+        });
+        let count = self.queue.len();
+        self.queue.clear();
+        self.since = None;
+        match written {
+            // the transaction held the database, so the batch took the ids up to `last`
+            Ok(last) => {
+                for logged in self.standing.iter_mut() {
+                    if let Logged::Queued(i) = *logged {
+                        *logged = Logged::Written(last - (count - 1 - i) as i32);
+                    }
+                }
+            }
+            Err(e) => {
+                warn!("{}: logging {} strokes: {}", self.path, count, e);
+                self.standing.retain(|logged| matches!(logged, Logged::Written(_)));
+            }
+        }
+    }
+}
+impl Drop for StrokeLog {
+    fn drop(&mut self) {
+        self.flush();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::Stroke;
+
+    fn logged(log: &StrokeLog) -> Vec<(String, Option<String>, bool)> {
+        use crate::schema::strokes::dsl::*;
+        strokes
+            .order_by(id.asc())
+            .load::<Stroke>(&log.conn)
+            .unwrap()
+            .into_iter()
+            .map(|s| (s.chord, s.translation, s.undone))
+            .collect()
+    }
+
+    #[test]
+    fn undo_marks_queued_and_written_strokes() {
+        let mut log = StrokeLog::open(":memory:", "test").unwrap();
+        let kat = Chord::new(vec!["K-", "A-", "-T"]);
+        let star = Chord::new(vec!["*"]);
+        log.record(&kat, &Command::Output("cat".into()));
+        log.flush();
+        log.record(&kat, &Command::Output("cat".into()));
+        log.record(&star, &Command::Undo(Vec::new()));
+        assert_eq!(logged(&log).len(), 1);
+        log.record(&star, &Command::Undo(Vec::new()));
+        log.flush();
+        assert_eq!(
+            logged(&log),
+            [
+                ("KAT".into(), Some("cat".into()), true),
+                ("KAT".into(), Some("cat".into()), true),
+                ("*".into(), None, false),
+                ("*".into(), None, false),
+            ]
+        );
+    }
+
+    #[test]
+    fn writes_in_batches() {
+        let mut log = StrokeLog::open(":memory:", "test").unwrap();
+        let kat = Chord::new(vec!["K-", "A-", "-T"]);
+        for _ in 0..BATCH - 1 {
+            log.record(&kat, &Command::Error("KAT".into()));
+        }
+        assert!(logged(&log).is_empty());
+        log.record(&kat, &Command::Error("KAT".into()));
+        assert_eq!(logged(&log).len(), BATCH);
+    }
+}
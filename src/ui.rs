@@ -1,15 +1,20 @@
 #[cfg(feature = "sound")]
 use crate::workers::sound;
+use crate::workers::control::{self, SocketControl, SocketStatus};
 use crate::workers::{serial, window};
-use crate::workers::{Config, Shutdown, Worker, WorkerPool};
+use crate::workers::{Shutdown, Worker, WorkerPool};
+use crate::drill::Drill;
+use crate::engine::Engine;
+use crate::stats::Stats;
 use crate::{steno::*, *};
-use evdev::{uinput, Key as VirtualKey};
 use std::convert::TryInto;
-use std::io::stdout;
+use std::fs::File;
+use std::str::FromStr;
 use std::thread;
 use std::time::Duration;
 use termion::{
     event::Key as PhysicalKey,
+    get_tty,
     raw::{IntoRawMode, RawTerminal},
 };
 use tui::text::*;
@@ -21,212 +26,375 @@ use tui::{
     Terminal
 };
 
+/// A row of the TUI. Rows are laid out top to bottom in the order given.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Pane {
+    /// Output history.
+    Output,
+    /// The last chord on the steno order.
+    Steno,
+    /// Chord history with the suggestions or drill beside it.
+    History,
+    /// Lookup, or the add-translation dialog while it is open.
+    Lookup,
+    /// Dictionaries and session statistics.
+    Dictionaries,
+}
+impl FromStr for Pane {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "output" => Ok(Self::Output),
+            "steno" => Ok(Self::Steno),
+            "history" => Ok(Self::History),
+            "lookup" => Ok(Self::Lookup),
+            "dictionaries" => Ok(Self::Dictionaries),
+            _ => Err(format!("unknown pane {}", s)),
+        }
+    }
+}
+impl Pane {
+    fn constraint(&self, sources: usize) -> Constraint {
+        match self {
+            Self::Output | Self::History => Constraint::Min(0),
+            Self::Steno => Constraint::Ratio(1, 16),
+            Self::Lookup => Constraint::Percentage(25),
+            Self::Dictionaries => Constraint::Length(sources.max(3) as u16 + 2),
+        }
+    }
+}
+
+/// How the TUI is laid out and the serial port `Ctrl+r` reconnects to.
+#[derive(Clone, Debug)]
+pub struct Options {
+    pub port: String,
+    /// Time between frames.
+    pub refresh: Duration,
+    pub panes: Vec<Pane>,
+}
+impl Default for Options {
+    fn default() -> Self {
+        Self {
+            port: "/dev/ttyACM0".to_string(),
+            refresh: Duration::from_millis(100),
+            panes: vec![Pane::Output, Pane::Steno, Pane::History, Pane::Lookup, Pane::Dictionaries],
+        }
+    }
+}
+
 pub struct Tui {
-    terminal: Terminal<TermionBackend<RawTerminal<std::io::Stdout>>>,
-    keyboard: uinput::VirtualDevice,
+    // drawn on the tty so stdout is free for the stdout output
+    terminal: Terminal<TermionBackend<RawTerminal<File>>>,
+    engine: Engine,
     worker_pool: WorkerPool,
-    dictionary: Dictionary,
+    options: Options,
     output: History<String, ListState>,
     last: History<String, TableState>,
     raw: History<String, ListState>,
     input: Vec<String>,
+    search: Search,
+    lookup: Lookup,
+    suggestions: Vec<Suggestion>,
+    adding: Option<AddDialog>,
+    /// Serial devices to pick from with `Ctrl+p`.
+    ports: Option<History<String, ListState>>,
+    /// The serial worker's last reported state.
+    connection: serial::Connection,
 }
+/// How many translations back the suggestions pane looks for phrases.
+const SUGGESTION_DEPTH: usize = 4;
 enum Signal {
     Shutdown,
 }
-struct Keyboard(uinput::VirtualDevice);
-type KeyStream = Vec<Option<&'static (Option<VirtualKey>, VirtualKey)>>;
-impl Keyboard {
-    fn create_stream(cmd: ActionSymbol, text: String) -> Vec<VirtualKey> {
-        let mut temp = Vec::new();
-        let text_stream: KeyStream = text.split("").map(|c| KEY_CODE.get(c)).collect();
-        use ActionSymbol::*;
-        match cmd {
-            Suffix => {
-                temp.push(VirtualKey::KEY_BACKSPACE);
-            }
-            Delete => temp.extend(vec![VirtualKey::KEY_LEFTCTRL, VirtualKey::KEY_BACKSPACE]),
-            _ => {}
-        }
-        for key_combo in text_stream {
-            if let Some(combo) = key_combo {
-                match combo {
-                    (Some(modifier), letter) => {
-                        temp.extend(vec![modifier, letter]);
-                    }
-                    (None, letter) => {
-                        temp.push(*letter);
-                    }
-                }
-            }
+/// What the lookup pane matches its input against, switched with `Tab`.
+#[derive(Clone, Copy, PartialEq)]
+enum Search {
+    Chord,
+    Translation,
+}
+/// The lookup pane's results, only queried again when the input changes or
+/// the dictionaries do.
+#[derive(Default)]
+struct Lookup {
+    query: Option<(String, Search)>,
+    results: Vec<(String, models::Entry)>,
+}
+impl Lookup {
+    fn update(&mut self, input: String, search: Search, dictionary: &Dictionary) {
+        let query = Some((input, search));
+        if self.query == query {
+            return;
         }
-        temp
+        self.results = match &query {
+            Some((input, _)) if input.is_empty() => Vec::new(),
+            Some((input, Search::Chord)) => dictionary.find(input),
+            Some((input, Search::Translation)) => dictionary.find_translation(input),
+            None => Vec::new(),
+        };
+        self.query = query;
+    }
+
+    fn invalidate(&mut self) {
+        self.query = None;
     }
 }
-impl Default for Tui {
-    fn default() -> Self {
-        let dictionary = Dictionary::from_file("./main.json");
-        let backend = TermionBackend::new(stdout().into_raw_mode().unwrap());
-        let mut terminal = tui::Terminal::new(backend).unwrap();
-        let raw = History::new(Vec::new(), 10);
-        let output = History::new(Vec::new(), 10);
-        let last = History::new(Vec::new(), 1);
-        let config = Config::default();
-        let worker_pool = WorkerPool {
-            #[cfg(feature = "sound")]
-            audio: sound::AudioWorker::start(config.clone()),
-            serial: serial::SerialWorker::start(config.clone()),
-            window: window::InputWorker::start(config.clone()),
-        };
-        let keyboard = uinput::VirtualDeviceBuilder::new()
-            .unwrap()
-            .name("wayplover")
-            .with_keys(&VIRT_KEY_MAP)
-            .unwrap()
-            .build()
-            .unwrap();
-        terminal.clear().unwrap();
+/// The add-translation dialog field typed keys go to.
+#[derive(Clone, Copy, PartialEq)]
+enum Field {
+    Strokes,
+    Translation,
+}
+/// A new entry for the top dictionary. Chords go to the strokes rather than
+/// being translated while it is open.
+#[derive(Clone)]
+struct AddDialog {
+    strokes: String,
+    translation: String,
+    field: Field,
+    /// What the enabled dictionaries already have for the strokes.
+    conflicts: Vec<(String, String)>,
+    message: Option<String>,
+}
+impl AddDialog {
+    fn new() -> Self {
         Self {
-            terminal,
-            keyboard,
-            raw,
-            last,
-            output,
-            dictionary,
-            worker_pool,
-            input: Vec::new(),
+            strokes: String::new(),
+            translation: String::new(),
+            field: Field::Strokes,
+            conflicts: Vec::new(),
+            message: None,
         }
     }
 }
 impl Tui {
-    pub fn new(worker_pool: WorkerPool, dictionary: Dictionary) -> Self {
-        let backend = TermionBackend::new(stdout().into_raw_mode().unwrap());
-        let mut terminal = tui::Terminal::new(backend).unwrap();
+    pub fn new(worker_pool: WorkerPool, engine: Engine, options: Options) -> Self {
+        let tty = TermionBackend::new(get_tty().unwrap().into_raw_mode().unwrap());
+        let mut terminal = tui::Terminal::new(tty).unwrap();
         let output = History::new(Vec::new(), 500);
         let raw = History::new(Vec::new(), 500);
         let last = History::new(Vec::new(), 1);
-        let keyboard = uinput::VirtualDeviceBuilder::new()
-            .unwrap()
-            .name("wayplover")
-            .with_keys(&VIRT_KEY_MAP)
-            .unwrap()
-            .build()
-            .unwrap();
         terminal.clear().unwrap();
         Self {
             terminal,
-            keyboard,
+            engine,
+            options,
             raw,
             last,
             output,
-            dictionary,
             worker_pool,
             input: Vec::new(),
+            search: Search::Chord,
+            lookup: Lookup::default(),
+            suggestions: Vec::new(),
+            adding: None,
+            ports: None,
+            connection: serial::Connection::Disconnected,
+        }
+    }
+
+    fn handle_status(&mut self, status: serial::DeviceStatus) {
+        match status {
+            serial::DeviceStatus::Input(s) => self.handle_chord(s),
+            serial::DeviceStatus::Malformed(bytes) => {
+                warn!("Dropped malformed frame {:02x?}", bytes);
+            }
+            serial::DeviceStatus::Connection(connection) => info!("Keyboard {}", connection),
         }
     }
 
     fn handle_chord(&mut self, chord: Chord) {
-        use evdev::*;
-        let mut seq = Vec::new();
-        let (sym, text) = {
-            let s = chord.resolve(&mut self.dictionary);
+        if let Some(dialog) = &mut self.adding {
+            if !dialog.strokes.is_empty() {
+                dialog.strokes.push('/');
+            }
+            dialog.strokes.push_str(&chord.steno());
+            dialog.conflicts = self.engine.dictionary.definitions(&dialog.strokes);
+            self.last.replace(chord.raw());
+            return;
+        }
+        let command = self.engine.translate(&chord);
+        if let Some(control) = &self.worker_pool.control {
+            control.send(SocketControl::Publish(control::stroke(&chord)));
+            if let Some(command) = &command {
+                control.send(SocketControl::Publish(control::translation(&chord, command)));
+            }
+        }
+        if let Some(Command::Output(text)) | Some(Command::Replace(_, text)) = &command {
+            if text == "{PLOVER:ADD_TRANSLATION}" {
+                self.adding = Some(AddDialog::new());
+            }
+        }
+        if let Some(command) = command {
+            self.suggestions = self.engine.dictionary.suggestions(SUGGESTION_DEPTH);
             #[cfg(feature = "sound")]
-            match s.clone() {
+            match command.clone() {
                 Command::Error(_) => self
                     .worker_pool
                     .audio
                     .send(sound::AudioControl::Play(Sound::Error)),
-                Command::Output(text) => self
+                Command::Output(text) | Command::Replace(_, text) => self
                     .worker_pool
                     .audio
                     .send(sound::AudioControl::Speak(text.clone())),
                 _ => {}
             }
-            s.as_text()
-        };
-
-        let text_stream: Vec<Option<&(Option<VirtualKey>, VirtualKey)>> =
-            text.split("").map(|c| KEY_CODE.get(c)).collect();
-        match sym {
-            ActionSymbol::Suffix => {
-                seq.push(VirtualKey::KEY_BACKSPACE);
-            }
-            ActionSymbol::Delete => {
-                seq.extend(vec![VirtualKey::KEY_LEFTCTRL, VirtualKey::KEY_BACKSPACE])
-            }
-            _ => {}
-        }
-        for key_combo in text_stream {
-            if let Some(combo) = key_combo {
-                match combo {
-                    (Some(modifier), letter) => {
-                        seq.extend(vec![modifier, letter]);
-                    }
-                    (None, letter) => {
-                        seq.push(*letter);
+            match (&command, command.as_text()) {
+                (Command::Undo(restored), _) => {
+                    self.output.pop(1);
+                    for text in restored {
+                        self.output.push(text.to_owned());
                     }
                 }
+                (_, (ActionSymbol::Replace(n), text)) => {
+                    self.output.pop(n);
+                    self.output.push(text);
+                }
+                (_, (_, text)) => self.output.push(text),
             }
         }
-        let mut held: (bool, Option<VirtualKey>) = (false, None);
-        let len = seq.len();
+        self.output.select(0);
+        self.raw.push(chord.plain());
+        self.raw.select(0);
+        self.last.replace(chord.raw())
+    }
 
-        for (i, &key) in seq.iter().enumerate() {
-            let down = InputEvent::new(EventType::KEY, key.code(), 1);
-            let up = InputEvent::new(EventType::KEY, key.code(), 0);
-            match key {
-                Key::KEY_LEFTCTRL | Key::KEY_LEFTALT | Key::KEY_LEFTSHIFT => {
-                    self.keyboard.emit(&[down]).unwrap();
-                    sleep(Duration::from_millis(1));
-                    held = (true, Some(key));
+    /// Keys for the add-translation dialog: `Tab` switches field, `Enter`
+    /// moves on to the translation and then adds the entry, `Esc` cancels.
+    fn handle_dialog(&mut self, key: PhysicalKey) {
+        let dialog = match &mut self.adding {
+            Some(dialog) => dialog,
+            None => return,
+        };
+        match (key, dialog.field) {
+            (PhysicalKey::Esc, _) => self.adding = None,
+            (PhysicalKey::Char('\t'), Field::Strokes) | (PhysicalKey::Char('\n'), Field::Strokes) => {
+                dialog.field = Field::Translation;
+            }
+            (PhysicalKey::Char('\t'), Field::Translation) => dialog.field = Field::Strokes,
+            (PhysicalKey::Char('\n'), Field::Translation) => {
+                if dialog.strokes.is_empty() || dialog.translation.is_empty() {
+                    dialog.message = Some("Needs strokes and a translation".to_string());
+                    return;
                 }
-                _ => {
-                    self.keyboard.emit(&[down, up]).unwrap();
-                    sleep(Duration::from_millis(1));
-                    if let (true, Some(key)) = held {
-                        self.keyboard
-                            .emit(&[InputEvent::new(EventType::KEY, key.code(), 0)])
-                            .unwrap();
-                        sleep(Duration::from_millis(1));
-                    } else if i.eq(&(len - 1)) {
-                        let down = InputEvent::new(EventType::KEY, Key::KEY_SPACE.code(), 1);
-                        let up = InputEvent::new(EventType::KEY, Key::KEY_SPACE.code(), 0);
-                        self.keyboard.emit(&[down, up]).unwrap();
-                        sleep(Duration::from_millis(1));
+                match self.engine.add(&dialog.strokes, &dialog.translation) {
+                    Ok(()) => {
+                        self.adding = None;
+                        self.lookup.invalidate();
                     }
+                    Err(e) => dialog.message = Some(e.to_string()),
                 }
             }
+            (PhysicalKey::Char(c), Field::Strokes) => {
+                dialog.strokes.push(c);
+                dialog.conflicts = self.engine.dictionary.definitions(&dialog.strokes);
+            }
+            (PhysicalKey::Char(c), Field::Translation) => dialog.translation.push(c),
+            (PhysicalKey::Backspace, Field::Strokes) => {
+                dialog.strokes.pop();
+                dialog.conflicts = self.engine.dictionary.definitions(&dialog.strokes);
+            }
+            (PhysicalKey::Backspace, Field::Translation) => {
+                dialog.translation.pop();
+            }
+            _ => {}
         }
-        match chord.resolve(&mut self.dictionary).as_text() {
-            (_, t) => {
-                self.output.push(t);
+    }
+
+    fn reconnect(&mut self, port: String) {
+        self.worker_pool
+            .serial
+            .send(serial::DeviceControl::Disconnect);
+        self.worker_pool
+            .serial
+            .send(serial::DeviceControl::Reconnect(port.clone()));
+        self.options.port = port;
+        thread::sleep(Duration::from_millis(50));
+    }
+
+    /// Keys for the port picker: arrows move, `Enter` connects, `Esc` cancels.
+    fn handle_picker(&mut self, key: PhysicalKey) {
+        let ports = match &mut self.ports {
+            Some(ports) => ports,
+            None => return,
+        };
+        let selected = ports.state.selected().unwrap_or(0);
+        match key {
+            PhysicalKey::Esc => self.ports = None,
+            PhysicalKey::Up => ports.select(selected.saturating_sub(1)),
+            PhysicalKey::Down => ports.select((selected + 1).min(ports.items.len().saturating_sub(1))),
+            PhysicalKey::Char('\n') => {
+                let port = ports.items.get(selected).cloned();
+                self.ports = None;
+                if let Some(port) = port {
+                    self.reconnect(port);
+                }
             }
+            _ => {}
         }
-        self.output.select(0);
-        self.raw.push(chord.plain());
-        self.raw.select(0);
-        self.last.replace(chord.raw())
     }
 
     fn handle_input(&mut self, key: PhysicalKey) -> Option<Signal> {
+        if self.adding.is_some() && key != PhysicalKey::Ctrl('c') {
+            self.handle_dialog(key);
+            return None;
+        }
+        if self.ports.is_some() && key != PhysicalKey::Ctrl('c') {
+            self.handle_picker(key);
+            return None;
+        }
         match key {
             PhysicalKey::Ctrl('c') => {
+                self.engine.finish();
                 #[cfg(feature = "sound")]
                 WorkerPool::shutdown(&self.worker_pool.audio);
-                WorkerPool::shutdown(&self.worker_pool.window);
+                if let Some(window) = &self.worker_pool.window {
+                    WorkerPool::shutdown(window);
+                }
                 WorkerPool::shutdown(&self.worker_pool.serial);
+                if let Some(keyboard) = &self.worker_pool.keyboard {
+                    WorkerPool::shutdown(keyboard);
+                }
+                if let Some(control) = &self.worker_pool.control {
+                    WorkerPool::shutdown(control);
+                }
                 thread::sleep(Duration::from_millis(50));
                 self.terminal.clear().unwrap();
                 Some(Signal::Shutdown)
             }
             PhysicalKey::Ctrl('r') => {
-                self.worker_pool
-                    .serial
-                    .send(serial::DeviceControl::Disconnect);
-                self.worker_pool
-                    .serial
-                    .send(serial::DeviceControl::Reconnect("/dev/ttyACM0"));
-                thread::sleep(Duration::from_millis(50));
+                self.reconnect(self.options.port.clone());
+                None
+            }
+            PhysicalKey::Ctrl('d') => {
+                let control = match self.connection {
+                    serial::Connection::Disabled => serial::DeviceControl::Enable,
+                    _ => serial::DeviceControl::Disable,
+                };
+                self.worker_pool.serial.send(control);
+                None
+            }
+            PhysicalKey::Ctrl('p') => {
+                let found = serial::discover();
+                let mut ports = History::new(found, usize::MAX);
+                let current = ports.items.iter().position(|p| *p == self.options.port);
+                ports.select(current.unwrap_or(0));
+                self.ports = Some(ports);
+                None
+            }
+            PhysicalKey::Ctrl('a') => {
+                self.adding = Some(AddDialog::new());
+                None
+            }
+            PhysicalKey::F(n) => {
+                self.engine.dictionary.toggle(usize::from(n).saturating_sub(1));
+                self.lookup.invalidate();
+                None
+            }
+            PhysicalKey::Char('\t') => {
+                self.search = match self.search {
+                    Search::Chord => Search::Translation,
+                    Search::Translation => Search::Chord,
+                };
                 None
             }
             PhysicalKey::Char(c) => {
@@ -246,60 +414,111 @@ impl Tui {
     }
 
     pub fn run(&mut self) {
-        use tui::widgets::*;
         loop {
             self.terminal.get_frame().set_cursor(0, 0);
-            if let Some(serial::DeviceStatus::Input(s)) = self.worker_pool.serial.recv() {
-                self.handle_chord(s);
+            let keyboard: Vec<serial::DeviceStatus> = match &self.worker_pool.keyboard {
+                Some(keyboard) => std::iter::from_fn(|| keyboard.recv()).collect(),
+                None => Vec::new(),
+            };
+            let serial: Vec<serial::DeviceStatus> = std::iter::from_fn(|| self.worker_pool.serial.recv()).collect();
+            for status in serial {
+                match status {
+                    serial::DeviceStatus::Connection(connection) => self.connection = connection,
+                    status => self.handle_status(status),
+                }
             }
-            if let Some(window::InputStatus::Input(key)) = self.worker_pool.window.recv() {
-                let state = self.handle_input(key);
-                use Signal::*;
-                match state {
-                    Some(Shutdown) => {
-                        return;
-                    }
-                    _ => {}
+            for status in keyboard {
+                self.handle_status(status);
+            }
+            let requests: Vec<SocketStatus> = self.worker_pool.control.iter().flat_map(|c| c.recv()).collect();
+            for SocketStatus::Request(client, command) in requests {
+                let reply = self.engine.control(&command);
+                self.lookup.invalidate();
+                if let Some(control) = &self.worker_pool.control {
+                    control.send(SocketControl::Reply(client, reply));
+                }
+            }
+            let window = self.worker_pool.window.as_ref().and_then(|w| w.recv());
+            if let Some(window::InputStatus::Input(key)) = window {
+                if let Some(Signal::Shutdown) = self.handle_input(key) {
+                    return;
                 }
             }
             let mut output = self.output.clone();
             let mut raw = self.raw.clone();
             let last = self.last.clone();
-            let input = self.input.clone();
-            let dict = self.dictionary.clone();
+            let input = self.input.join("");
+            self.lookup.update(input.clone(), self.search, &self.engine.dictionary);
+            let lookup = &self.lookup;
+            let search = self.search;
+            let sources: Vec<(String, bool)> = self
+                .engine
+                .dictionary
+                .sources()
+                .iter()
+                .map(|s| (s.name.clone(), s.enabled))
+                .collect();
+            let adding = self.adding.clone();
+            let mut ports = self.ports.clone();
+            let port = self.options.port.clone();
+            let connection = &self.connection;
+            let suggestions = &self.suggestions;
+            let stats = &self.engine.stats;
+            let drill = &self.engine.drill;
+            let panes = &self.options.panes;
             self.terminal
                 .draw(|f| {
                     let size = f.size();
                     let segments = Layout::default()
                         .direction(Direction::Vertical)
                         .margin(1)
-                        .constraints(
-                            [
-                            Constraint::Min(0),
-                            Constraint::Ratio(1, 16),
-                            Constraint::Min(0),
-                            Constraint::Percentage(25),
-                            ]
-                            .as_ref(),
-                        )
+                        .constraints(panes.iter().map(|p| p.constraint(sources.len())).collect::<Vec<_>>())
                         .split(size);
 
                     let mut widths: Vec<Constraint> = Vec::new();
                     for _ in 0..STENO_ORDER.len() {
                         widths.push(Constraint::Ratio(1, STENO_ORDER.len().try_into().unwrap()));
                     }
-                    f.render_stateful_widget(Self::draw_output(&output), segments[0], output.state());
-                    f.render_widget(Self::draw_last(&last).widths(&widths), segments[1]);
-                    f.render_stateful_widget(Self::draw_histroy(&raw), segments[2], raw.state());
-                    
-                    let (input, results) = Self::draw_lookup(input, &dict, segments[3]);
-                    f.render_widget(input.0, input.1);
-                    f.render_widget(results.0, results.1);
-
+                    let halves = |area| {
+                        Layout::default()
+                            .direction(Direction::Horizontal)
+                            .constraints([Constraint::Percentage(50), Constraint::Percentage(50)].as_ref())
+                            .split(area)
+                    };
+                    for (pane, &area) in panes.iter().zip(segments.iter()) {
+                        match pane {
+                            Pane::Output => f.render_stateful_widget(Self::draw_output(&output), area, output.state()),
+                            Pane::Steno => f.render_widget(Self::draw_last(&last, connection).widths(&widths), area),
+                            Pane::History => {
+                                let middle = halves(area);
+                                f.render_stateful_widget(Self::draw_histroy(&raw), middle[0], raw.state());
+                                match drill {
+                                    Some(drill) => f.render_widget(Self::draw_drill(drill), middle[1]),
+                                    None => f.render_widget(Self::draw_suggestions(suggestions), middle[1]),
+                                }
+                            }
+                            Pane::Lookup => {
+                                if let Some(ports) = &mut ports {
+                                    f.render_stateful_widget(Self::draw_ports(ports, &port), area, ports.state());
+                                } else if let Some(dialog) = &adding {
+                                    f.render_widget(Self::draw_add(dialog, &sources[0].0), area);
+                                } else {
+                                    let (input, results) = Self::draw_lookup(input.clone(), search, &lookup.results, area);
+                                    f.render_widget(input.0, input.1);
+                                    f.render_widget(results.0, results.1);
+                                }
+                            }
+                            Pane::Dictionaries => {
+                                let bottom = halves(area);
+                                f.render_widget(Self::draw_dictionaries(&sources), bottom[0]);
+                                f.render_widget(Self::draw_stats(stats), bottom[1]);
+                            }
+                        }
+                    }
                 })
             .unwrap();
             //self.terminal.get_frame().set_cursor(1, 1);
-            thread::sleep(Duration::from_millis(100));
+            thread::sleep(self.options.refresh);
             self.terminal.autoresize().unwrap();
         }
     }
@@ -329,7 +548,7 @@ impl Tui {
             .map(|i| {
                 let s = i
                     .split("")
-                    .map(|s| format!("{}", s))
+                    .map(|s| s.to_string())
                     .collect::<Vec<String>>();
                 let s = s.join("");
                 ListItem::new(s)
@@ -343,7 +562,41 @@ impl Tui {
             .highlight_symbol(">>")
     }
 
-    fn draw_last(stroke: &History<String, TableState>) -> Table {
+    fn draw_drill(drill: &Drill) -> Paragraph<'static> {
+        let (done, total) = drill.progress();
+        let mut lines = match drill.target() {
+            Some((word, outline)) => vec![
+                Spans::from(Span::styled(word.to_owned(), Style::default().fg(Color::Yellow))),
+                Spans::from(outline.unwrap_or("no outline").to_owned()),
+                Spans::from(drill.written().to_owned()),
+            ],
+            None => vec![Spans::from("Lesson done")],
+        };
+        lines.push(Spans::default());
+        lines.push(Spans::from(format!(
+            "{}/{} words, {:.0}% clean, {:.1}s a word",
+            done,
+            total,
+            drill.accuracy(),
+            drill.mean_latency().as_secs_f64()
+        )));
+        Paragraph::new(lines).block(Block::default().title(format!("Drill: {}", drill.lesson)).borders(Borders::all()))
+    }
+
+    fn draw_suggestions(suggestions: &[Suggestion]) -> List<'static> {
+        let items: Vec<ListItem> = suggestions
+            .iter()
+            .map(|s| {
+                let shorter = s.outlines[0].split('/').count() < s.used.split('/').count();
+                let color = if shorter { Color::Green } else { Color::White };
+                ListItem::new(format!("{}: {} (not {})", s.text, s.outlines.join(", "), s.used))
+                    .style(Style::default().fg(color))
+            })
+            .collect();
+        List::new(items).block(Block::default().title("Suggestions").borders(Borders::all()))
+    }
+
+    fn draw_last<'a>(stroke: &'a History<String, TableState>, connection: &serial::Connection) -> Table<'a> {
         use tui::style::*;
         use tui::widgets::*;
         let steno_order = STENO_ORDER.descending_keys();
@@ -352,10 +605,10 @@ impl Tui {
             .rev()
             .map(|letter| {
                 if s.contains(&letter.to_string()) {
-                    Cell::from(format!("{}", letter.replace("-", "")))
+                    Cell::from(letter.replace("-", ""))
                         .style(Style::default().fg(Color::White))
                 } else {
-                    Cell::from(format!("{}", letter.replace("-", "")))
+                    Cell::from(letter.replace("-", ""))
                         .style(Style::default().fg(Color::Blue))
                 }
             })
@@ -364,31 +617,102 @@ impl Tui {
 
         Table::new(vec![])
             .header(header)
-            .block(Block::default().title("Steno Order").borders(Borders::ALL))
+            .block(Block::default().title(format!("Steno Order: {}", connection)).borders(Borders::ALL))
             .column_spacing(1)
             .style(Style::default().fg(Color::White).bg(Color::Black))
     }
 
-    fn draw_lookup(input:Vec<String>, dictionary:&Dictionary, area: Rect) -> ((Paragraph<'static>, Rect), (List<'static>, Rect)) {
+    fn draw_lookup(input: String, search: Search, results: &[(String, models::Entry)], area: Rect) -> ((Paragraph<'static>, Rect), (List<'static>, Rect)) {
         let layout = Layout::default()
             .constraints([Constraint::Min(0), Constraint::Percentage(60)].as_ref())
             .split(area);
-        use models::Entry;
-        let full_input = input.join("");
         let c1 = Block::default().title("Lookup").borders(Borders::all());
-        let c2 = c1.clone().title("Input");
-        let mut results : Vec<Entry> = Vec::new();
-        if !full_input.is_empty()  {
-            results = dictionary.find(&full_input);
-        }
+        let c2 = c1.clone().title(match search {
+            Search::Chord => "Input: chord (Tab)",
+            Search::Translation => "Input: translation (Tab)",
+        });
+        // only what fits, a short query can match most of the dictionary
         let items: Vec<ListItem> = results
-            .into_iter()
-            .map(|e| ListItem::new(e.chord))
+            .iter()
+            .take(layout[1].height as usize)
+            .map(|(source, e)| ListItem::new(format!("{} => {} [{}]", e.chord, e.translation, source)))
             .collect();
         let l = List::new(items).block(c1).highlight_symbol(">>");
-        let p = Paragraph::new(vec![Spans::from(vec![Span::raw(full_input)])]).block(c2);
+        let p = Paragraph::new(vec![Spans::from(vec![Span::raw(input)])]).block(c2);
         ((p, layout[0]), (l, layout[1]))
     }
+
+    fn draw_ports(ports: &History<String, ListState>, current: &str) -> List<'static> {
+        let items: Vec<ListItem> = ports
+            .items
+            .iter()
+            .map(|port| {
+                let color = if port == current { Color::Yellow } else { Color::White };
+                ListItem::new(port.clone()).style(Style::default().fg(color))
+            })
+            .collect();
+        let title = if items.is_empty() {
+            "Serial ports: none found, Esc to close"
+        } else {
+            "Serial ports: Enter connect, Esc cancel"
+        };
+        List::new(items)
+            .block(Block::default().title(title).borders(Borders::all()))
+            .highlight_symbol(">>")
+    }
+
+    fn draw_add(dialog: &AddDialog, top: &str) -> Paragraph<'static> {
+        let field = |name: &str, value: &str, active: bool| {
+            let style = if active {
+                Style::default().fg(Color::Yellow)
+            } else {
+                Style::default().fg(Color::White)
+            };
+            Spans::from(vec![Span::styled(format!("{:<13}", name), style), Span::raw(value.to_owned())])
+        };
+        let mut lines = vec![
+            field("Strokes:", &dialog.strokes, dialog.field == Field::Strokes),
+            field("Translation:", &dialog.translation, dialog.field == Field::Translation),
+            Spans::default(),
+        ];
+        for (source, text) in &dialog.conflicts {
+            let line = format!("{} => {} [{}]", dialog.strokes, text, source);
+            lines.push(Spans::from(Span::styled(line, Style::default().fg(Color::Red))));
+        }
+        if let Some(message) = &dialog.message {
+            lines.push(Spans::from(Span::styled(message.clone(), Style::default().fg(Color::Red))));
+        }
+        let title = format!(
+            "Add to {}: Tab switch, Enter next/add, Esc cancel",
+            top
+        );
+        Paragraph::new(lines).block(Block::default().title(title).borders(Borders::all()))
+    }
+
+    fn draw_stats(stats: &Stats) -> Paragraph<'static> {
+        let lines = vec![
+            Spans::from(format!("WPM {:.0} last minute, {:.0} session", stats.rolling_wpm(), stats.wpm())),
+            Spans::from(format!(
+                "Strokes/s {:.2} last minute, {:.2} session",
+                stats.rolling_strokes_per_second(),
+                stats.strokes_per_second()
+            )),
+            Spans::from(format!("Errors {:.1}%, undos {:.1}%", stats.error_rate(), stats.undo_rate())),
+        ];
+        Paragraph::new(lines).block(Block::default().title("Session").borders(Borders::all()))
+    }
+
+    fn draw_dictionaries(sources: &[(String, bool)]) -> List<'static> {
+        let items: Vec<ListItem> = sources
+            .iter()
+            .enumerate()
+            .map(|(i, (name, enabled))| {
+                let color = if *enabled { Color::White } else { Color::DarkGray };
+                ListItem::new(format!("F{} {}", i + 1, name)).style(Style::default().fg(color))
+            })
+            .collect();
+        List::new(items).block(Block::default().title("Dictionaries").borders(Borders::all()))
+    }
 }
 #[derive(Clone)]
 struct History<T, S> {
@@ -398,6 +722,7 @@ struct History<T, S> {
 }
 trait Selectable<S> {
     fn select(&mut self, i: usize);
+    #[allow(dead_code)]
     fn unselect(&mut self);
 }
 impl<T: Clone, S: Default> History<T, S> {
@@ -414,6 +739,10 @@ impl<T: Clone, S: Default> History<T, S> {
         }
         self.items.push(items);
     }
+    pub fn pop(&mut self, n: usize) {
+        let keep = self.items.len().saturating_sub(n);
+        self.items.truncate(keep);
+    }
     pub fn replace(&mut self, items: Vec<T>) {
         self.items.clear();
         self.items.extend(items);
@@ -143,10 +143,10 @@ where
             (Some(idx1), Some(idx2)) => {
                 let idx1 = (self.extract_comparable)(idx1);
                 let idx2 = (self.extract_comparable)(idx2);
-                return idx1.cmp(&idx2);
+                idx1.cmp(&idx2)
             }
-            (None, _) => return Ordering::Greater,
-            (_, None) => return Ordering::Less,
+            (None, _) => Ordering::Greater,
+            (_, None) => Ordering::Less,
         }
     }
     pub fn get(&self, key: &K) -> Option<&V> {
@@ -155,6 +155,9 @@ where
     pub fn len(&self) -> usize {
         self.map.len()
     }
+    pub fn is_empty(&self) -> bool {
+        self.map.is_empty()
+    }
 
     /// Keys of this map in descending order
     pub fn descending_keys(&'a self) -> DescendingKeys<'a, K, C> {
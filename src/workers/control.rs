@@ -0,0 +1,249 @@
+use super::Config;
+use super::Worker;
+use crate::socket;
+use crate::steno::{Chord, Command};
+use json::{object, JsonValue};
+use log::{info, warn};
+use std::io::{ErrorKind, Read, Write};
+use std::os::unix::net::UnixStream;
+use std::sync::mpsc;
+use std::thread;
+
+/// A command read from a client, answered by the main loop with a `Reply`.
+#[derive(Debug)]
+pub enum SocketStatus {
+    Request(usize, JsonValue),
+}
+#[derive(Debug)]
+pub enum SocketControl {
+    /// An event for every subscribed client.
+    Publish(JsonValue),
+    Reply(usize, JsonValue),
+    Shutdown,
+}
+
+/// How much output may wait for a client to read before it is dropped.
+const PENDING_LIMIT: usize = 1 << 20;
+
+struct Client {
+    id: usize,
+    stream: UnixStream,
+    buffer: Vec<u8>,
+    /// Output the client's socket wasn't ready for yet.
+    pending: Vec<u8>,
+    subscribed: bool,
+}
+impl Client {
+    /// Queue a message and send what the socket takes, returning whether the
+    /// client is still there and keeping up.
+    fn write(&mut self, message: &JsonValue) -> bool {
+        self.pending.extend(format!("{}\n", message.dump()).as_bytes());
+        self.flush()
+    }
+
+    /// Send as much pending output as the socket takes without blocking, so
+    /// lines are never cut short.
+    fn flush(&mut self) -> bool {
+        while !self.pending.is_empty() {
+            match self.stream.write(&self.pending) {
+                Ok(0) => return false,
+                Ok(n) => {
+                    self.pending.drain(..n);
+                }
+                Err(e) if e.kind() == ErrorKind::WouldBlock => break,
+                Err(e) if e.kind() == ErrorKind::Interrupted => {}
+                Err(_) => return false,
+            }
+        }
+        self.pending.len() <= PENDING_LIMIT
+    }
+
+    /// Read whatever arrived, returning complete lines and whether the client
+    /// is still there.
+    fn read(&mut self) -> (Vec<String>, bool) {
+        let mut bytes = [0; 1024];
+        let open = loop {
+            match self.stream.read(&mut bytes) {
+                Ok(0) => break false,
+                Ok(n) => self.buffer.extend(&bytes[..n]),
+                Err(e) if e.kind() == ErrorKind::WouldBlock => break true,
+                Err(_) => break false,
+            }
+        };
+        let mut lines = Vec::new();
+        while let Some(end) = self.buffer.iter().position(|&b| b == b'\n') {
+            let line: Vec<u8> = self.buffer.drain(..=end).collect();
+            lines.push(String::from_utf8_lossy(&line).trim().to_string());
+        }
+        (lines, open)
+    }
+}
+
+/// The JSON event for a stroke coming in.
+pub fn stroke(chord: &Chord) -> JsonValue {
+    object! { "event" => "stroke", "stroke" => chord.steno() }
+}
+
+/// The JSON event for what a stroke translated to.
+pub fn translation(chord: &Chord, command: &Command) -> JsonValue {
+    let (kind, text) = match command {
+        Command::Output(text) => ("output", text.to_owned()),
+        Command::Replace(_, text) => ("replace", text.to_owned()),
+        Command::Error(text) => ("untranslated", text.to_owned()),
+        Command::Undo(restored) => ("undo", restored.concat()),
+    };
+    object! {
+        "event" => "translation",
+        "stroke" => chord.steno(),
+        "command" => kind,
+        "text" => text,
+    }
+}
+
+/// A Unix socket taking line-delimited JSON commands. Clients sending
+/// `{"command": "subscribe"}` get every stroke and translation as events;
+/// everything else is handed to the main loop and its reply written back.
+pub struct ControlWorker {
+    tx: mpsc::Sender<SocketControl>,
+    rx: mpsc::Receiver<SocketStatus>,
+    handler: thread::JoinHandle<()>,
+}
+impl Worker<SocketControl, SocketStatus> for ControlWorker {
+    fn start(config: Config) -> Self {
+        let ((tx, thread_rx), (thread_tx, rx)) = (mpsc::channel(), mpsc::channel());
+        let handler = thread::Builder::new()
+            .name("ControlThread".to_string())
+            .spawn(move || {
+                let path = config.control.unwrap_or_default();
+                let listener = match socket::listen(&path) {
+                    Ok(listener) => listener,
+                    Err(e) => {
+                        warn!("{}: {}", path, e);
+                        return;
+                    }
+                };
+                info!("Listening on {}", path);
+                let mut clients: Vec<Client> = Vec::new();
+                let mut next = 0;
+                loop {
+                    while let Ok((stream, _)) = listener.accept() {
+                        if stream.set_nonblocking(true).is_ok() {
+                            clients.push(Client {
+                                id: next,
+                                stream,
+                                buffer: Vec::new(),
+                                pending: Vec::new(),
+                                subscribed: false,
+                            });
+                            next += 1;
+                        }
+                    }
+                    let mut closed = Vec::new();
+                    for client in clients.iter_mut() {
+                        let (lines, open) = client.read();
+                        for line in lines.iter().filter(|l| !l.is_empty()) {
+                            match json::parse(line) {
+                                Ok(command) if command["command"] == "subscribe" => {
+                                    client.subscribed = true;
+                                    client.write(&object! { "ok" => true });
+                                }
+                                Ok(command) => {
+                                    let _ = thread_tx.send(SocketStatus::Request(client.id, command));
+                                }
+                                Err(e) => {
+                                    client.write(&object! { "ok" => false, "error" => e.to_string() });
+                                }
+                            }
+                        }
+                        if !open || !client.flush() {
+                            closed.push(client.id);
+                        }
+                    }
+                    clients.retain(|c| !closed.contains(&c.id));
+                    while let Ok(e) = thread_rx.try_recv() {
+                        match e {
+                            SocketControl::Publish(event) => {
+                                clients.retain_mut(|c| !c.subscribed || c.write(&event));
+                            }
+                            SocketControl::Reply(id, reply) => {
+                                clients.retain_mut(|c| c.id != id || c.write(&reply));
+                            }
+                            SocketControl::Shutdown => {
+                                socket::remove(&path);
+                                return;
+                            }
+                        }
+                    }
+                    thread::sleep(config.tick_rate);
+                }
+            })
+            .unwrap();
+        Self { tx, rx, handler }
+    }
+
+    fn send(&self, e: SocketControl) {
+        let _ = self.tx.send(e);
+    }
+    fn recv(&self) -> Option<SocketStatus> {
+        self.rx.try_recv().ok()
+    }
+    fn shutdown(&self) {
+        self.send(SocketControl::Shutdown);
+    }
+    fn join(self) {
+        let _ = self.handler.join();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::BufRead;
+
+    #[test]
+    fn slow_reader_gets_whole_lines() {
+        let (stream, other) = UnixStream::pair().unwrap();
+        stream.set_nonblocking(true).unwrap();
+        let mut client = Client {
+            id: 0,
+            stream,
+            buffer: Vec::new(),
+            pending: Vec::new(),
+            subscribed: true,
+        };
+        let text = "x".repeat(1000);
+        let mut sent = 0;
+        while client.pending.is_empty() {
+            assert!(client.write(&object! { "n" => sent, "text" => text.as_str() }));
+            sent += 1;
+        }
+        let reader = thread::spawn(move || {
+            std::io::BufReader::new(other)
+                .lines()
+                .take(sent)
+                .map(|line| json::parse(&line.unwrap()).unwrap()["n"].as_usize().unwrap())
+                .collect::<Vec<usize>>()
+        });
+        while !client.pending.is_empty() {
+            assert!(client.flush());
+            thread::yield_now();
+        }
+        assert_eq!(reader.join().unwrap(), (0..sent).collect::<Vec<usize>>());
+    }
+
+    #[test]
+    fn stalled_reader_is_dropped() {
+        let (stream, _other) = UnixStream::pair().unwrap();
+        stream.set_nonblocking(true).unwrap();
+        let mut client = Client {
+            id: 0,
+            stream,
+            buffer: Vec::new(),
+            pending: Vec::new(),
+            subscribed: true,
+        };
+        let text = "x".repeat(64 * 1024);
+        let kept_up = (0..64).all(|_| client.write(&object! { "text" => text.as_str() }));
+        assert!(!kept_up);
+    }
+}
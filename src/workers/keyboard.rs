@@ -0,0 +1,439 @@
+use super::serial::{Connection, DeviceControl, DeviceStatus};
+use super::Config;
+use super::Worker;
+use crate::steno::Chord;
+use evdev::{Device, InputEventKind, Key};
+use log::{info, warn};
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::io;
+use std::os::unix::io::AsRawFd;
+use std::sync::mpsc;
+use std::thread;
+
+/// `EVIOCGRAB`, `_IOW('E', 0x90, int)`.
+const EVIOCGRAB: libc::c_ulong = 0x4004_4590;
+/// Plover's default keyboard layout, steno key to QWERTY keys.
+const PLOVER_LAYOUT: [(&str, &[&str]); 23] = [
+    ("#", &["1", "2", "3", "4", "5", "6", "7", "8", "9", "0", "-", "="]),
+    ("S-", &["q", "a"]),
+    ("T-", &["w"]),
+    ("K-", &["s"]),
+    ("P-", &["e"]),
+    ("W-", &["d"]),
+    ("H-", &["r"]),
+    ("R-", &["f"]),
+    ("A-", &["c"]),
+    ("O-", &["v"]),
+    ("*", &["t", "y", "g", "h"]),
+    ("-E", &["n"]),
+    ("-U", &["m"]),
+    ("-F", &["u"]),
+    ("-R", &["j"]),
+    ("-P", &["i"]),
+    ("-B", &["k"]),
+    ("-L", &["o"]),
+    ("-G", &["l"]),
+    ("-T", &["p"]),
+    ("-S", &[";"]),
+    ("-D", &["["]),
+    ("-Z", &["'"]),
+];
+
+/// When a chord is sent.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum ChordMode {
+    /// Once every key has been released.
+    #[default]
+    AllUp,
+    /// As soon as the first key is released, keys still held count towards
+    /// the next chord.
+    FirstUp,
+}
+
+/// Which physical keys press which steno keys.
+#[derive(Clone, Debug)]
+pub struct Layout {
+    keys: HashMap<Key, String>,
+}
+impl Default for Layout {
+    fn default() -> Self {
+        let mut keys = HashMap::new();
+        for (steno, names) in PLOVER_LAYOUT.iter() {
+            for name in names.iter() {
+                keys.insert(key_name(name).unwrap(), steno.to_string());
+            }
+        }
+        Self { keys }
+    }
+}
+impl Layout {
+    /// Read a layout in Plover's keymap format, an object of steno keys to
+    /// lists of key names: `{"S-": ["q", "a"], "T-": ["w"]}`.
+    pub fn from_file(path: &str) -> Result<Self, String> {
+        let contents = fs::read_to_string(path).map_err(|e| format!("{}: {}", path, e))?;
+        let parsed = json::parse(&contents).map_err(|e| format!("{}: {}", path, e))?;
+        let mut keys = HashMap::new();
+        for (steno, names) in parsed.entries() {
+            for name in names.members() {
+                let name = name.as_str().unwrap_or_default();
+                match key_name(name) {
+                    Some(key) => {
+                        keys.insert(key, steno.to_string());
+                    }
+                    None => return Err(format!("{}: unknown key {:?}", path, name)),
+                }
+            }
+        }
+        Ok(Self { keys })
+    }
+
+    pub fn get(&self, key: Key) -> Option<&str> {
+        self.keys.get(&key).map(String::as_str)
+    }
+
+    /// The chord pressed by `keys`, leaving out any without a steno key.
+    fn chord(&self, keys: HashSet<Key>) -> Chord {
+        Chord::new(keys.into_iter().filter_map(|k| self.get(k)).collect())
+    }
+}
+
+/// Where to read steno keys from and how.
+#[derive(Clone, Debug, Default)]
+pub struct Options {
+    /// An evdev device such as `/dev/input/event3`, the first keyboard found
+    /// when unset.
+    pub device: Option<String>,
+    pub layout: Layout,
+    pub mode: ChordMode,
+}
+
+/// The evdev key for a key name as Plover writes them.
+fn key_name(name: &str) -> Option<Key> {
+    let key = match name {
+        "a" => Key::KEY_A,
+        "b" => Key::KEY_B,
+        "c" => Key::KEY_C,
+        "d" => Key::KEY_D,
+        "e" => Key::KEY_E,
+        "f" => Key::KEY_F,
+        "g" => Key::KEY_G,
+        "h" => Key::KEY_H,
+        "i" => Key::KEY_I,
+        "j" => Key::KEY_J,
+        "k" => Key::KEY_K,
+        "l" => Key::KEY_L,
+        "m" => Key::KEY_M,
+        "n" => Key::KEY_N,
+        "o" => Key::KEY_O,
+        "p" => Key::KEY_P,
+        "q" => Key::KEY_Q,
+        "r" => Key::KEY_R,
+        "s" => Key::KEY_S,
+        "t" => Key::KEY_T,
+        "u" => Key::KEY_U,
+        "v" => Key::KEY_V,
+        "w" => Key::KEY_W,
+        "x" => Key::KEY_X,
+        "y" => Key::KEY_Y,
+        "z" => Key::KEY_Z,
+        "1" => Key::KEY_1,
+        "2" => Key::KEY_2,
+        "3" => Key::KEY_3,
+        "4" => Key::KEY_4,
+        "5" => Key::KEY_5,
+        "6" => Key::KEY_6,
+        "7" => Key::KEY_7,
+        "8" => Key::KEY_8,
+        "9" => Key::KEY_9,
+        "0" => Key::KEY_0,
+        "-" => Key::KEY_MINUS,
+        "=" => Key::KEY_EQUAL,
+        "[" => Key::KEY_LEFTBRACE,
+        "]" => Key::KEY_RIGHTBRACE,
+        ";" => Key::KEY_SEMICOLON,
+        "'" => Key::KEY_APOSTROPHE,
+        "`" => Key::KEY_GRAVE,
+        "\\" => Key::KEY_BACKSLASH,
+        "," => Key::KEY_COMMA,
+        "." => Key::KEY_DOT,
+        "/" => Key::KEY_SLASH,
+        "space" => Key::KEY_SPACE,
+        "Tab" | "tab" => Key::KEY_TAB,
+        "Return" | "return" => Key::KEY_ENTER,
+        _ => return None,
+    };
+    Some(key)
+}
+
+/// Builds chords out of key presses and releases.
+struct Chorder {
+    mode: ChordMode,
+    down: HashSet<Key>,
+    stroke: HashSet<Key>,
+    /// Whether a key went down since the last chord was sent.
+    fresh: bool,
+}
+impl Chorder {
+    fn new(mode: ChordMode) -> Self {
+        Self {
+            mode,
+            down: HashSet::new(),
+            stroke: HashSet::new(),
+            fresh: false,
+        }
+    }
+
+    fn press(&mut self, key: Key) {
+        self.down.insert(key);
+        self.stroke.insert(key);
+        self.fresh = true;
+    }
+
+    /// Follow a key event, `value` being 1 for a press, 0 for a release and
+    /// 2 for autorepeat, returning the keys of a finished chord.
+    fn event(&mut self, key: Key, value: i32) -> Option<HashSet<Key>> {
+        match value {
+            1 => {
+                self.press(key);
+                None
+            }
+            0 => self.release(key),
+            _ => None,
+        }
+    }
+
+    fn release(&mut self, key: Key) -> Option<HashSet<Key>> {
+        self.down.remove(&key);
+        match self.mode {
+            ChordMode::AllUp if self.down.is_empty() => {
+                self.fresh = false;
+                Some(std::mem::take(&mut self.stroke))
+            }
+            ChordMode::FirstUp if self.fresh => {
+                self.fresh = false;
+                let chord = std::mem::replace(&mut self.stroke, self.down.clone());
+                Some(chord)
+            }
+            ChordMode::FirstUp => {
+                self.stroke.remove(&key);
+                None
+            }
+            _ => None,
+        }
+    }
+
+    fn clear(&mut self) {
+        self.down.clear();
+        self.stroke.clear();
+        self.fresh = false;
+    }
+}
+
+/// The first device that looks like a full keyboard, skipping our own
+/// virtual one.
+fn find_keyboard() -> Option<Device> {
+    evdev::enumerate().find(|device| {
+        device.name() != Some("wayplover")
+            && device.supported_keys().is_some_and(|keys| {
+                keys.contains(Key::KEY_Q) && keys.contains(Key::KEY_P) && keys.contains(Key::KEY_SPACE)
+            })
+    })
+}
+
+fn grab(device: &Device, grab: bool) -> io::Result<()> {
+    match unsafe { libc::ioctl(device.as_raw_fd(), EVIOCGRAB, grab as libc::c_int) } {
+        -1 => Err(io::Error::last_os_error()),
+        _ => Ok(()),
+    }
+}
+
+fn set_nonblocking(device: &Device) -> io::Result<()> {
+    let fd = device.as_raw_fd();
+    let flags = unsafe { libc::fcntl(fd, libc::F_GETFL) };
+    match unsafe { libc::fcntl(fd, libc::F_SETFL, flags | libc::O_NONBLOCK) } {
+        -1 => Err(io::Error::last_os_error()),
+        _ => Ok(()),
+    }
+}
+
+/// Steno from an ordinary (NKRO) keyboard. The keyboard is grabbed so its keys
+/// only reach wayplover; `Esc` releases and grabs it again.
+pub struct KeyboardWorker {
+    tx: mpsc::Sender<DeviceControl>,
+    rx: mpsc::Receiver<DeviceStatus>,
+    handler: thread::JoinHandle<()>,
+}
+impl Worker<DeviceControl, DeviceStatus> for KeyboardWorker {
+    fn start(config: Config) -> Self {
+        let ((tx, thread_rx), (thread_tx, rx)) = (mpsc::channel(), mpsc::channel());
+        let handler = thread::Builder::new()
+            .name("KeyboardThread".to_string())
+            .spawn(move || {
+                let keyboard = config.keyboard.unwrap_or_default();
+                let device = match keyboard.device.as_deref() {
+                    Some(path) => Device::open(path).ok(),
+                    None => find_keyboard(),
+                };
+                let mut device = match device {
+                    Some(device) => device,
+                    None => {
+                        warn!("No keyboard found at {:?}", keyboard.device);
+                        let _ = thread_tx.send(DeviceStatus::Connection(Connection::Disconnected));
+                        return;
+                    }
+                };
+                info!("Steno keyboard {:?}", device.name());
+                if let Err(e) = set_nonblocking(&device) {
+                    warn!("{}", e);
+                }
+                let mut enabled = grab(&device, true).is_ok();
+                let mut chorder = Chorder::new(keyboard.mode);
+                'read: loop {
+                    if let Ok(e) = thread_rx.try_recv() {
+                        info!("{:?}", e);
+                        use DeviceControl::*;
+                        match e {
+                            Disable | Disconnect => {
+                                let _ = grab(&device, false);
+                                enabled = false;
+                                chorder.clear();
+                            }
+                            Enable | Reconnect(_) => {
+                                enabled = grab(&device, true).is_ok();
+                            }
+                            Shutdown => {
+                                let _ = grab(&device, false);
+                                break;
+                            }
+                        }
+                    }
+                    let events: Vec<_> = match device.fetch_events() {
+                        Ok(events) => events.collect(),
+                        Err(e) if e.kind() == io::ErrorKind::WouldBlock => {
+                            thread::sleep(config.tick_rate);
+                            continue;
+                        }
+                        Err(e) => {
+                            warn!("{}", e);
+                            let _ = thread_tx.send(DeviceStatus::Connection(Connection::Disconnected));
+                            break;
+                        }
+                    };
+                    for event in events {
+                        let key = match event.kind() {
+                            InputEventKind::Key(key) => key,
+                            _ => continue,
+                        };
+                        if key == Key::KEY_ESC {
+                            if event.value() == 0 {
+                                enabled = if enabled {
+                                    let _ = grab(&device, false);
+                                    false
+                                } else {
+                                    grab(&device, true).is_ok()
+                                };
+                                chorder.clear();
+                                info!("Steno keyboard {}", if enabled { "grabbed" } else { "released" });
+                            }
+                            continue;
+                        }
+                        if !enabled || keyboard.layout.get(key).is_none() {
+                            continue;
+                        }
+                        let chord = chorder.event(key, event.value()).map(|keys| keyboard.layout.chord(keys));
+                        if let Some(chord) = chord.filter(|c| !c.is_empty()) {
+                            // nobody left to read chords
+                            if thread_tx.send(DeviceStatus::Input(chord)).is_err() {
+                                let _ = grab(&device, false);
+                                break 'read;
+                            }
+                        }
+                    }
+                }
+            })
+            .unwrap();
+        Self { tx, rx, handler }
+    }
+
+    fn send(&self, e: DeviceControl) {
+        info!("[KeyboardEvent] {:?}", e);
+        let _ = self.tx.send(e);
+    }
+    fn shutdown(&self) {
+        self.send(DeviceControl::Shutdown);
+    }
+    fn join(self) {
+        let _ = self.handler.join();
+    }
+
+    fn recv(&self) -> Option<DeviceStatus> {
+        self.rx.try_recv().ok()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+
+    /// Feed key events through a chorder, returning the chords sent.
+    fn chords(mode: ChordMode, events: &[(&str, i32)]) -> Vec<String> {
+        let layout = Layout::default();
+        let mut chorder = Chorder::new(mode);
+        events
+            .iter()
+            .filter_map(|&(name, value)| chorder.event(key_name(name).unwrap(), value))
+            .map(|keys| layout.chord(keys).steno())
+            .collect()
+    }
+
+    #[test]
+    fn all_up_sends_once_every_key_is_released() {
+        let events = [("s", 1), ("c", 1), ("p", 1), ("c", 2), ("s", 0), ("c", 0), ("p", 0)];
+        assert_eq!(chords(ChordMode::AllUp, &events), ["KAT"]);
+    }
+
+    #[test]
+    fn first_up_sends_at_the_first_release() {
+        // -T held on into the next chord, with S- added to it
+        let events = [("s", 1), ("c", 1), ("p", 1), ("s", 0), ("q", 1), ("c", 0), ("p", 0), ("q", 0)];
+        assert_eq!(chords(ChordMode::FirstUp, &events), ["KAT", "SAT"]);
+    }
+
+    #[test]
+    fn first_up_drops_keys_released_without_a_new_press() {
+        let events = [("s", 1), ("c", 1), ("s", 0), ("c", 0), ("p", 1), ("p", 0)];
+        assert_eq!(chords(ChordMode::FirstUp, &events), ["KA", "-T"]);
+    }
+
+    #[test]
+    fn keys_outside_the_layout_are_left_out() {
+        let events = [("s", 1), ("x", 1), ("x", 0), ("s", 0)];
+        assert_eq!(chords(ChordMode::AllUp, &events), ["K"]);
+    }
+
+    fn layout_file(name: &str, contents: &str) -> Result<Layout, String> {
+        let path = env::temp_dir().join(format!("wayplover-{}-{}.json", name, std::process::id()));
+        fs::write(&path, contents).unwrap();
+        let layout = Layout::from_file(path.to_str().unwrap());
+        fs::remove_file(&path).unwrap();
+        layout
+    }
+
+    #[test]
+    fn layout_from_file() {
+        let layout = layout_file("layout", r#"{"S-": ["a", "q"], "-T": ["Return"], "*": []}"#).unwrap();
+        assert_eq!(layout.get(Key::KEY_A), Some("S-"));
+        assert_eq!(layout.get(Key::KEY_Q), Some("S-"));
+        assert_eq!(layout.get(Key::KEY_ENTER), Some("-T"));
+        assert_eq!(layout.get(Key::KEY_P), None);
+    }
+
+    #[test]
+    fn layout_with_an_unknown_key() {
+        let error = layout_file("unknown", r#"{"S-": ["a", "F13"]}"#).unwrap_err();
+        assert!(error.ends_with("unknown key \"F13\""), "{}", error);
+        assert!(layout_file("invalid", "{").is_err());
+    }
+}
@@ -1,4 +1,6 @@
 use std::time::Duration;
+pub mod control;
+pub mod keyboard;
 pub mod serial;
 #[cfg(feature = "sound")]
 pub mod sound;
@@ -9,6 +11,8 @@ pub trait Worker<In, Out> {
     fn send(&self, e: In);
     fn recv(&self) -> Option<Out>;
     fn shutdown(&self);
+    /// Wait for the worker's thread to finish after a shutdown.
+    fn join(self);
 }
 pub trait Shutdown<In, Out> {
     fn shutdown<T: Worker<In, Out>>(worker: &T) {
@@ -19,11 +23,39 @@ pub struct WorkerPool {
     #[cfg(feature = "sound")]
     pub audio: sound::AudioWorker,
     pub serial: serial::SerialWorker,
-    pub window: window::InputWorker,
+    pub keyboard: Option<keyboard::KeyboardWorker>,
+    pub control: Option<control::ControlWorker>,
+    /// Terminal keys, only read when there is a TUI.
+    pub window: Option<window::InputWorker>,
+}
+impl WorkerPool {
+    /// Shut every worker down and wait for their threads.
+    pub fn join(self) {
+        #[cfg(feature = "sound")]
+        {
+            Self::shutdown(&self.audio);
+            self.audio.join();
+        }
+        Self::shutdown(&self.serial);
+        self.serial.join();
+        if let Some(keyboard) = self.keyboard {
+            Self::shutdown(&keyboard);
+            keyboard.join();
+        }
+        if let Some(control) = self.control {
+            Self::shutdown(&control);
+            control.join();
+        }
+        if let Some(window) = self.window {
+            Self::shutdown(&window);
+            window.join();
+        }
+    }
 }
 
 impl Shutdown<window::InputControl, window::InputStatus> for WorkerPool {}
 impl Shutdown<serial::DeviceControl, serial::DeviceStatus> for WorkerPool {}
+impl Shutdown<control::SocketControl, control::SocketStatus> for WorkerPool {}
 #[cfg(feature = "sound")]
 impl Shutdown<sound::AudioControl, sound::AudioStatus> for WorkerPool {}
 
@@ -31,12 +63,27 @@ impl Shutdown<sound::AudioControl, sound::AudioStatus> for WorkerPool {}
 pub struct Config {
     pub tick_rate: Duration,
     pub port: String,
+    /// Baud rate, data bits, parity, stop bits and flow control.
+    pub line: ::serial::PortSettings,
+    pub protocol: serial::Protocol,
+    /// Read steno from a keyboard as well as the serial port.
+    pub keyboard: Option<keyboard::Options>,
+    /// Where to listen for control commands.
+    pub control: Option<String>,
+    /// Whether the audio worker makes any sound.
+    #[cfg_attr(not(feature = "sound"), allow(dead_code))]
+    pub sound: bool,
 }
 impl Default for Config {
     fn default() -> Config {
         Self {
             tick_rate: Duration::from_millis(250),
             port: "/dev/ttyACM0".to_string(),
+            line: serial::port_settings(9600, 8, "none", 1, "none").unwrap(),
+            protocol: serial::Protocol::GeminiPr,
+            keyboard: None,
+            control: None,
+            sound: true,
         }
     }
 }
@@ -2,13 +2,20 @@
 extern crate test_case;
 
 use super::Config;
-use log::{info, error};
+use log::{debug, info, warn};
 use super::Worker;
 use crate::steno::Chord;
-use serial;
-use std::io::Read;
+use serial::{self, SerialPort};
+use std::ffi::CString;
+use std::fmt;
+use std::fs;
+use std::io::{self, Read};
+use std::os::unix::ffi::OsStrExt;
+use std::path::Path;
+use std::str::FromStr;
 use std::sync::mpsc;
 use std::thread;
+use std::time::{Duration, Instant};
 
 const BYTES_PER_STROKE: usize = 6;
 const STENO_MAP: [&str; 42] = [
@@ -16,19 +23,364 @@ const STENO_MAP: [&str; 42] = [
     "*", "*", "res", "res", "pwr", "*", "*", "-E", "-U", "-F", "-R", "-P", "-B", "-L", "-G", "-T",
     "-S", "-D", "#", "#", "#", "#", "#", "#", "-Z",
 ];
+/// TX Bolt keys by set, bit 0 first. Each byte carries its set in the top two bits.
+const TX_BOLT_MAP: [[&str; 6]; 4] = [
+    ["S-", "T-", "K-", "P-", "W-", "H-"],
+    ["R-", "A-", "O-", "*", "-E", "-U"],
+    ["-F", "-R", "-P", "-B", "-L", "-G"],
+    ["-T", "-S", "-D", "-Z", "#", ""],
+];
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Protocol {
+    GeminiPr,
+    TxBolt,
+}
+impl FromStr for Protocol {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "gemini" => Ok(Self::GeminiPr),
+            "txbolt" => Ok(Self::TxBolt),
+            _ => Err(format!("unknown protocol {}", s)),
+        }
+    }
+}
+
+/// Frames Gemini PR packets. Only the first byte of a packet has the high bit
+/// set, so anything between sync bytes that isn't a whole packet is dropped
+/// and handed back as malformed.
+#[derive(Default)]
+struct GeminiPr {
+    frame: Vec<u8>,
+    skipped: Vec<u8>,
+}
+impl GeminiPr {
+    fn push(&mut self, byte: u8) -> Option<DeviceStatus> {
+        if byte & 0x80 != 0 {
+            let dropped = std::mem::take(&mut self.frame);
+            self.skipped.extend(dropped);
+            self.frame.push(byte);
+            if !self.skipped.is_empty() {
+                return Some(DeviceStatus::Malformed(std::mem::take(&mut self.skipped)));
+            }
+            return None;
+        }
+        if self.frame.is_empty() {
+            // started mid-packet, wait for the next sync byte
+            self.skipped.push(byte);
+            return None;
+        }
+        self.frame.push(byte);
+        if self.frame.len() < BYTES_PER_STROKE {
+            return None;
+        }
+        let frame = std::mem::take(&mut self.frame);
+        let mut temp = Vec::new();
+        for (i, b) in frame.iter().enumerate() {
+            for j in 1..8 {
+                if b & (0x80 >> j) != 0 {
+                    temp.push(STENO_MAP[i * 7 + j - 1]);
+                }
+            }
+        }
+        Some(DeviceStatus::Input(Chord::new(temp)))
+    }
+}
+
+/// Collects TX Bolt bytes into strokes. A stroke sends its sets in rising
+/// order, so a set at or below the last one starts the next stroke; set 3 or
+/// a zero byte ends it outright.
+#[derive(Default)]
+struct TxBolt {
+    keys: Vec<&'static str>,
+    set: Option<u8>,
+}
+impl TxBolt {
+    fn push(&mut self, byte: u8) -> Option<Chord> {
+        if byte == 0 {
+            return self.flush();
+        }
+        let set = byte >> 6;
+        let finished = match self.set {
+            Some(last) if set <= last => self.flush(),
+            _ => None,
+        };
+        for (bit, key) in TX_BOLT_MAP[set as usize].iter().enumerate() {
+            if byte & (1 << bit) != 0 && !key.is_empty() {
+                self.keys.push(key);
+            }
+        }
+        self.set = Some(set);
+        if set == 3 {
+            return self.flush();
+        }
+        finished
+    }
+
+    fn flush(&mut self) -> Option<Chord> {
+        self.set = None;
+        let chord = Chord::new(self.keys.drain(..).collect());
+        if chord.is_empty() {
+            None
+        } else {
+            Some(chord)
+        }
+    }
+}
 
 pub enum DeviceStatus {
     Input(Chord),
+    /// Bytes thrown away while resynchronising on a packet boundary.
+    Malformed(Vec<u8>),
+    /// The device's connection changed.
+    Connection(Connection),
+}
+/// Where a device stands, as reported to the TUI.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Connection {
+    Connected(String),
+    /// Closed on request, until told to reconnect.
     Disconnected,
+    /// Gone or not there yet, retrying until it is.
+    Reconnecting(String),
+    /// Closed until enabled again.
+    Disabled,
+}
+impl fmt::Display for Connection {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Connected(path) => write!(f, "{} connected", path),
+            Self::Disconnected => write!(f, "disconnected"),
+            Self::Reconnecting(path) => write!(f, "waiting for {}", path),
+            Self::Disabled => write!(f, "disabled"),
+        }
+    }
 }
 #[derive(Debug)]
 pub enum DeviceControl {
     Disable,
     Enable,
-    Reconnect(&'static str),
+    Reconnect(String),
     Disconnect,
     Shutdown,
 }
+/// Serial line settings from their command line and config spellings: data
+/// bits 5 to 8, parity `none`, `odd` or `even`, stop bits 1 or 2 and flow
+/// control `none`, `software` or `hardware`.
+pub fn port_settings(
+    baud: u32,
+    data_bits: u8,
+    parity: &str,
+    stop_bits: u8,
+    flow_control: &str,
+) -> Result<serial::PortSettings, String> {
+    Ok(serial::PortSettings {
+        baud_rate: serial::BaudRate::from_speed(baud as usize),
+        char_size: match data_bits {
+            5 => serial::Bits5,
+            6 => serial::Bits6,
+            7 => serial::Bits7,
+            8 => serial::Bits8,
+            _ => return Err(format!("{} data bits, should be 5 to 8", data_bits)),
+        },
+        parity: match parity {
+            "none" => serial::ParityNone,
+            "odd" => serial::ParityOdd,
+            "even" => serial::ParityEven,
+            _ => return Err(format!("unknown parity {}", parity)),
+        },
+        stop_bits: match stop_bits {
+            1 => serial::Stop1,
+            2 => serial::Stop2,
+            _ => return Err(format!("{} stop bits, should be 1 or 2", stop_bits)),
+        },
+        flow_control: match flow_control {
+            "none" => serial::FlowNone,
+            "software" => serial::FlowSoftware,
+            "hardware" => serial::FlowHardware,
+            _ => return Err(format!("unknown flow control {}", flow_control)),
+        },
+    })
+}
+
+/// Serial devices that could be a steno machine: the stable names under
+/// `/dev/serial/by-id` first, then any `ttyACM` or `ttyUSB` not already
+/// listed by one.
+pub fn discover() -> Vec<String> {
+    discover_in(Path::new("/dev"))
+}
+
+/// `discover` under `dev` rather than `/dev`.
+fn discover_in(dev: &Path) -> Vec<String> {
+    let mut ports = Vec::new();
+    let mut seen = Vec::new();
+    if let Ok(entries) = fs::read_dir(dev.join("serial").join("by-id")) {
+        let mut entries: Vec<_> = entries.flatten().map(|e| e.path()).collect();
+        entries.sort();
+        for path in entries {
+            if let Ok(target) = fs::canonicalize(&path) {
+                seen.push(target);
+            }
+            ports.push(path.to_string_lossy().to_string());
+        }
+    }
+    if let Ok(entries) = fs::read_dir(dev) {
+        let mut entries: Vec<_> = entries
+            .flatten()
+            .map(|e| e.path())
+            .filter(|path| {
+                let name = path.file_name().unwrap_or_default().to_string_lossy();
+                name.starts_with("ttyACM") || name.starts_with("ttyUSB")
+            })
+            .collect();
+        entries.sort();
+        for path in entries {
+            let target = fs::canonicalize(&path).unwrap_or_else(|_| path.clone());
+            if !seen.contains(&target) {
+                ports.push(path.to_string_lossy().to_string());
+            }
+        }
+    }
+    ports
+}
+
+/// Open the port with `settings`.
+fn open(path: &str, settings: &serial::PortSettings) -> serial::Result<serial::SystemPort> {
+    let mut port = serial::open(path)?;
+    if let Err(e) = port.configure(settings) {
+        warn!("{}: {}", path, e);
+    }
+    Ok(port)
+}
+
+/// First wait before retrying a port that failed to open, doubled on each
+/// failure up to `MAX_BACKOFF`.
+const BACKOFF: Duration = Duration::from_millis(250);
+const MAX_BACKOFF: Duration = Duration::from_secs(5);
+
+/// The serial thread's state, the port being open only while connected.
+enum State<P = serial::SystemPort> {
+    Connected(P),
+    Disconnected,
+    Reconnecting { retry: Instant, backoff: Duration },
+    Disabled,
+}
+/// What moves the serial thread from one `State` to the next.
+enum Event<P> {
+    Control(DeviceControl),
+    Opened(P),
+    /// Opening the port failed.
+    Failed,
+    /// The open port went away.
+    Lost,
+}
+impl<P> State<P> {
+    fn reconnect(now: Instant) -> Self {
+        Self::Reconnecting {
+            retry: now,
+            backoff: BACKOFF,
+        }
+    }
+
+    /// The state after `event`. Disabled only ends with `Enable`, a port
+    /// picked meanwhile is connected to then.
+    fn next(self, event: Event<P>, now: Instant) -> Self {
+        use DeviceControl::*;
+        match (self, event) {
+            (Self::Disabled, Event::Control(Enable)) => Self::reconnect(now),
+            (Self::Disabled, _) => Self::Disabled,
+            (_, Event::Control(Disable)) => Self::Disabled,
+            // dropping the port closes it
+            (_, Event::Control(Disconnect)) => Self::Disconnected,
+            (_, Event::Control(Reconnect(_))) => Self::reconnect(now),
+            (Self::Reconnecting { .. }, Event::Opened(port)) => Self::Connected(port),
+            (Self::Reconnecting { backoff, .. }, Event::Failed) => Self::Reconnecting {
+                retry: now + backoff,
+                backoff: (backoff * 2).min(MAX_BACKOFF),
+            },
+            (Self::Connected(_), Event::Lost) => Self::reconnect(now),
+            (state, _) => state,
+        }
+    }
+
+    fn connection(&self, path: &str) -> Connection {
+        match self {
+            Self::Connected(_) => Connection::Connected(path.to_string()),
+            Self::Disconnected => Connection::Disconnected,
+            Self::Reconnecting { .. } => Connection::Reconnecting(path.to_string()),
+            Self::Disabled => Connection::Disabled,
+        }
+    }
+
+    /// Keep `watch` while waiting for the port and only then.
+    fn watch(&self, watch: &mut Option<Watch>, path: &str) {
+        match self {
+            Self::Reconnecting { .. } if watch.is_none() => *watch = Watch::new(path),
+            Self::Reconnecting { .. } => {}
+            _ => *watch = None,
+        }
+    }
+}
+
+/// An inotify watch on the port's directory and `/dev`, so a retry can
+/// happen as soon as a device node turns up instead of on the next backoff.
+/// Without one the port is only polled.
+struct Watch {
+    fd: libc::c_int,
+}
+impl Watch {
+    fn new(path: &str) -> Option<Self> {
+        let fd = unsafe { libc::inotify_init1(libc::IN_NONBLOCK | libc::IN_CLOEXEC) };
+        if fd < 0 {
+            warn!("inotify: {}", io::Error::last_os_error());
+            return None;
+        }
+        let mask = libc::IN_CREATE | libc::IN_ATTRIB | libc::IN_MOVED_TO;
+        let watch = Self { fd };
+        let parent = Path::new(path).parent().filter(|dir| dir.exists());
+        let mut watched = false;
+        for dir in parent.into_iter().chain(Some(Path::new("/dev"))) {
+            let name = match CString::new(dir.as_os_str().as_bytes()) {
+                Ok(name) => name,
+                Err(_) => continue,
+            };
+            if unsafe { libc::inotify_add_watch(fd, name.as_ptr(), mask) } < 0 {
+                warn!("inotify {}: {}", dir.display(), io::Error::last_os_error());
+            } else {
+                watched = true;
+            }
+        }
+        if !watched {
+            warn!("Not watching for {}, polling for it", path);
+            return None;
+        }
+        Some(watch)
+    }
+
+    /// Whether anything was created since last asked.
+    fn changed(&self) -> bool {
+        let mut buf = [0u8; 4096];
+        let mut changed = false;
+        while unsafe { libc::read(self.fd, buf.as_mut_ptr() as *mut libc::c_void, buf.len()) } > 0 {
+            changed = true;
+        }
+        changed
+    }
+}
+impl Drop for Watch {
+    fn drop(&mut self) {
+        unsafe { libc::close(self.fd) };
+    }
+}
+/// Whether a read error means the device went away, rather than the read
+/// timing out.
+fn lost(e: &io::Error) -> bool {
+    !matches!(
+        e.kind(),
+        io::ErrorKind::TimedOut | io::ErrorKind::WouldBlock | io::ErrorKind::Interrupted
+    )
+}
 pub struct SerialWorker {
     tx: mpsc::Sender<DeviceControl>,
     rx: mpsc::Receiver<DeviceStatus>,
@@ -40,59 +392,100 @@ impl Worker<DeviceControl, DeviceStatus> for SerialWorker {
         let handler = thread::Builder::new()
             .name("DeviceThread".to_string())
             .spawn(move || {
-                let mut port = serial::open(&config.port).ok();
+                let mut path = config.port.clone();
+                let mut state: State = State::reconnect(Instant::now());
+                let mut watch = Watch::new(&path);
+                let mut reported: Option<Connection> = None;
+                let mut bolt = TxBolt::default();
+                let mut gemini = GeminiPr::default();
                 loop {
-                    if let Ok(e) = thread_rx.try_recv() {
+                    while let Ok(e) = thread_rx.try_recv() {
                         info!("{:?}", e);
-                        println!("{:?}", e);
-                        use DeviceControl::*;
                         match e {
-                            Disconnect => {
-                                //let _  = port.take();
-                                thread_tx.send(DeviceStatus::Disconnected);
-                            }
-                            Reconnect(path) => {
-                                //port = serial::open(path).ok();
-                            }
-                            Shutdown => {
+                            DeviceControl::Shutdown => return,
+                            DeviceControl::Reconnect(ref to) => {
+                                path = to.clone();
+                                watch = None;
                             }
                             _ => {}
                         }
+                        state = state.next(Event::Control(e), Instant::now());
+                        state.watch(&mut watch, &path);
                     }
-                    let mut byte_chord: [u8; BYTES_PER_STROKE] = [0; BYTES_PER_STROKE];
-                    match port.as_mut() {
-                        Some(p) => {
-                            if let Err(e) = p.read_exact(&mut byte_chord){
-                                
-                            }
+                    let connection = state.connection(&path);
+                    if reported.as_ref() != Some(&connection) {
+                        info!("Serial {}", connection);
+                        let _ = thread_tx.send(DeviceStatus::Connection(connection.clone()));
+                        reported = Some(connection);
+                    }
+                    let (statuses, event) = match &mut state {
+                        State::Connected(port) => {
+                            let (statuses, lost) = match config.protocol {
+                                Protocol::GeminiPr => {
+                                    let mut bytes = [0; 64];
+                                    match port.read(&mut bytes) {
+                                        Ok(0) => (Vec::new(), true),
+                                        Ok(n) => (bytes[..n].iter().filter_map(|&b| gemini.push(b)).collect(), false),
+                                        Err(e) => (Vec::new(), lost(&e)),
+                                    }
+                                }
+                                Protocol::TxBolt => {
+                                    let mut byte = [0; 1];
+                                    let (chord, lost) = match port.read(&mut byte) {
+                                        Ok(1) => (bolt.push(byte[0]), false),
+                                        Ok(_) => (bolt.flush(), true),
+                                        // the read timed out, nothing more is coming for this stroke
+                                        Err(e) => (bolt.flush(), lost(&e)),
+                                    };
+                                    (chord.map(DeviceStatus::Input).into_iter().collect(), lost)
+                                }
+                            };
+                            (statuses, if lost { Some(Event::Lost) } else { None })
                         }
-                        None => loop {
-
-                            use std::time::Duration;
-                            thread::sleep(Duration::from_millis(30));
-                            use DeviceControl::*;
-                            if let Ok(e) = thread_rx.try_recv() {
-                                match e {
-                                    Reconnect(path) => {
-                                        port = serial::open(path).ok();
+                        State::Reconnecting { retry, backoff } => {
+                            if watch.as_ref().is_some_and(Watch::changed) {
+                                *retry = Instant::now();
+                            }
+                            let event = if Instant::now() < *retry {
+                                None
+                            } else {
+                                match open(&path, &config.line) {
+                                    Ok(port) => Some(Event::Opened(port)),
+                                    Err(e) => {
+                                        debug!("{}: {}, retrying in {:?}", path, e, backoff);
+                                        Some(Event::Failed)
                                     }
-                                    _ => {}
                                 }
+                            };
+                            if !matches!(event, Some(Event::Opened(_))) {
+                                thread::sleep(config.tick_rate);
                             }
+                            (Vec::new(), event)
                         }
-                    }
-
-                    let mut temp = Vec::new();
-                    for (i, b) in byte_chord.iter().enumerate() {
-                        for j in 1..8 {
-                            if b & (0x80 >> j) != 0 {
-                                temp.push(STENO_MAP[i * 7 + j - 1]);
+                        State::Disconnected | State::Disabled => {
+                            thread::sleep(config.tick_rate);
+                            (Vec::new(), None)
+                        }
+                    };
+                    for status in statuses {
+                        match status {
+                            DeviceStatus::Input(chord) if chord.is_empty() => {}
+                            status => {
+                                let _ = thread_tx.send(status);
                             }
                         }
                     }
-                    let chord = Chord::new(temp);
-                    if !chord.is_empty() {
-                        thread_tx.send(DeviceStatus::Input(chord)).unwrap();
+                    if let Some(event) = event {
+                        match event {
+                            Event::Lost => warn!("Lost {}", path),
+                            Event::Opened(_) => {
+                                gemini = GeminiPr::default();
+                                bolt = TxBolt::default();
+                            }
+                            _ => {}
+                        }
+                        state = state.next(event, Instant::now());
+                        state.watch(&mut watch, &path);
                     }
                 }
             })
@@ -107,13 +500,224 @@ impl Worker<DeviceControl, DeviceStatus> for SerialWorker {
     fn shutdown(&self) {
         self.send(DeviceControl::Shutdown);
     }
+    fn join(self) {
+        let _ = self.handler.join();
+    }
 
     fn recv(&self) -> Option<DeviceStatus> {
-        if let Ok(status) = self.rx.try_recv() {
-            Some(status)
-        } else {
-            None
-        }
+        self.rx.try_recv().ok()
     }
 }
 
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use test_case::test_case;
+
+    fn bolt(bytes: &[u8]) -> Vec<String> {
+        let mut bolt = TxBolt::default();
+        bytes.iter().filter_map(|&b| bolt.push(b)).map(|c| c.steno()).collect()
+    }
+
+    /// What a run of Gemini PR bytes comes out as, malformed runs as
+    /// `!` and their length.
+    fn gemini(bytes: &[u8]) -> Vec<String> {
+        let mut gemini = GeminiPr::default();
+        bytes
+            .iter()
+            .filter_map(|&b| gemini.push(b))
+            .map(|status| match status {
+                DeviceStatus::Input(chord) => chord.steno(),
+                DeviceStatus::Malformed(bytes) => format!("!{}", bytes.len()),
+                DeviceStatus::Connection(c) => c.to_string(),
+            })
+            .collect()
+    }
+
+    const SAT: [u8; 6] = [0x80, 0x40, 0x20, 0x00, 0x04, 0x00];
+
+    #[test]
+    fn gemini_packet() {
+        assert_eq!(gemini(&SAT), ["SAT"]);
+        assert_eq!(gemini(&[SAT, SAT].concat()), ["SAT", "SAT"]);
+    }
+
+    #[test]
+    fn gemini_number_bar() {
+        // # with S- and T- writes 1 and 2
+        assert_eq!(gemini(&[0xa0, 0x50, 0x00, 0x00, 0x00, 0x00]), ["12"]);
+    }
+
+    #[test]
+    fn gemini_resyncs_on_sync_byte() {
+        // joined mid-packet: the tail is dropped until the next packet starts
+        assert_eq!(gemini(&[&[0x20, 0x04, 0x00][..], &SAT].concat()), ["!3", "SAT"]);
+        // a packet cut short by the next one
+        assert_eq!(gemini(&[&SAT[..3], &SAT].concat()), ["!3", "SAT"]);
+        // a cut short packet with nothing after it stays pending
+        assert_eq!(gemini(&SAT[..4]), Vec::<String>::new());
+    }
+
+    #[test_case(&[0x04, 0x42, 0xc1], &["KAT"] ; "set 3 ends the stroke")]
+    #[test_case(&[0x01, 0x00], &["S"] ; "zero byte ends the stroke")]
+    #[test_case(&[0x01, 0x81, 0x01, 0x00], &["S-F", "S"] ; "lower set starts the next stroke")]
+    #[test_case(&[0x42, 0x42, 0x00], &["A", "A"] ; "repeated set starts the next stroke")]
+    #[test_case(&[0xd0, 0x01, 0x00], &["#", "S"] ; "number key")]
+    #[test_case(&[0x00, 0x00], &[] ; "empty strokes")]
+    fn tx_bolt(bytes: &[u8], strokes: &[&str]) {
+        assert_eq!(bolt(bytes), strokes);
+    }
+
+    /// The state after each event in turn, starting out reconnecting.
+    fn states(events: Vec<Event<()>>) -> Vec<Connection> {
+        let now = Instant::now();
+        let mut state = State::reconnect(now);
+        events
+            .into_iter()
+            .map(|event| {
+                state = std::mem::replace(&mut state, State::Disconnected).next(event, now);
+                state.connection("port")
+            })
+            .collect()
+    }
+
+    fn control(e: DeviceControl) -> Event<()> {
+        Event::Control(e)
+    }
+
+    #[test]
+    fn connects_and_reconnects_when_lost() {
+        assert_eq!(
+            states(vec![Event::Failed, Event::Opened(()), Event::Lost, Event::Opened(())]),
+            [
+                Connection::Reconnecting("port".to_string()),
+                Connection::Connected("port".to_string()),
+                Connection::Reconnecting("port".to_string()),
+                Connection::Connected("port".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn disconnect_until_reconnect() {
+        assert_eq!(
+            states(vec![
+                Event::Opened(()),
+                control(DeviceControl::Disconnect),
+                Event::Lost,
+                Event::Opened(()),
+                control(DeviceControl::Reconnect("other".to_string())),
+            ]),
+            [
+                Connection::Connected("port".to_string()),
+                Connection::Disconnected,
+                Connection::Disconnected,
+                Connection::Disconnected,
+                Connection::Reconnecting("port".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn disabled_until_enabled() {
+        assert_eq!(
+            states(vec![
+                Event::Opened(()),
+                control(DeviceControl::Disable),
+                control(DeviceControl::Reconnect("other".to_string())),
+                Event::Opened(()),
+                control(DeviceControl::Disconnect),
+                control(DeviceControl::Enable),
+                control(DeviceControl::Enable),
+            ]),
+            [
+                Connection::Connected("port".to_string()),
+                Connection::Disabled,
+                Connection::Disabled,
+                Connection::Disabled,
+                Connection::Disabled,
+                Connection::Reconnecting("port".to_string()),
+                Connection::Reconnecting("port".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn failures_back_off() {
+        let now = Instant::now();
+        let mut state: State<()> = State::reconnect(now);
+        let mut waits = Vec::new();
+        for _ in 0..6 {
+            state = state.next(Event::Failed, now);
+            if let State::Reconnecting { retry, .. } = state {
+                waits.push(retry - now);
+            }
+        }
+        let ms = Duration::from_millis;
+        assert_eq!(waits, [ms(250), ms(500), ms(1000), ms(2000), ms(4000), ms(5000)]);
+        // a new attempt starts over
+        state = state.next(Event::Control(DeviceControl::Reconnect("port".to_string())), now);
+        assert!(matches!(state, State::Reconnecting { retry, backoff } if retry == now && backoff == BACKOFF));
+    }
+
+    #[test]
+    fn line_settings() {
+        assert_eq!(
+            port_settings(9600, 8, "none", 1, "none"),
+            Ok(serial::PortSettings {
+                baud_rate: serial::Baud9600,
+                char_size: serial::Bits8,
+                parity: serial::ParityNone,
+                stop_bits: serial::Stop1,
+                flow_control: serial::FlowNone,
+            })
+        );
+        assert_eq!(
+            port_settings(115200, 7, "even", 2, "hardware"),
+            Ok(serial::PortSettings {
+                baud_rate: serial::Baud115200,
+                char_size: serial::Bits7,
+                parity: serial::ParityEven,
+                stop_bits: serial::Stop2,
+                flow_control: serial::FlowHardware,
+            })
+        );
+        let odd = port_settings(31250, 5, "odd", 1, "software").unwrap();
+        assert_eq!(odd.baud_rate, serial::BaudOther(31250));
+        assert_eq!(
+            (odd.char_size, odd.parity, odd.flow_control),
+            (serial::Bits5, serial::ParityOdd, serial::FlowSoftware)
+        );
+    }
+
+    #[test_case(9, "none", 1, "none", "9 data bits, should be 5 to 8" ; "nine data bits")]
+    #[test_case(4, "none", 1, "none", "4 data bits, should be 5 to 8" ; "four data bits")]
+    #[test_case(8, "mark", 1, "none", "unknown parity mark" ; "mark parity")]
+    #[test_case(8, "none", 3, "none", "3 stop bits, should be 1 or 2" ; "three stop bits")]
+    #[test_case(8, "none", 1, "xon", "unknown flow control xon" ; "unknown flow control")]
+    fn line_settings_errors(data_bits: u8, parity: &str, stop_bits: u8, flow_control: &str, error: &str) {
+        assert_eq!(
+            port_settings(9600, data_bits, parity, stop_bits, flow_control),
+            Err(error.to_string())
+        );
+    }
+
+    #[test]
+    fn discover_by_id_first() {
+        let dev = std::env::temp_dir().join(format!("wayplover-dev-{}", std::process::id()));
+        let by_id = dev.join("serial").join("by-id");
+        fs::create_dir_all(&by_id).unwrap();
+        for name in ["ttyACM0", "ttyUSB1", "ttyS0", "ttyUSB0"] {
+            fs::write(dev.join(name), "").unwrap();
+        }
+        std::os::unix::fs::symlink("../../ttyACM0", by_id.join("usb-Steno_Machine-if00")).unwrap();
+        let ports = discover_in(&dev);
+        fs::remove_dir_all(&dev).unwrap();
+        let expected: Vec<String> = [by_id.join("usb-Steno_Machine-if00"), dev.join("ttyUSB0"), dev.join("ttyUSB1")]
+            .iter()
+            .map(|path| path.to_string_lossy().to_string())
+            .collect();
+        assert_eq!(ports, expected);
+    }
+}
@@ -32,7 +32,7 @@ pub struct AudioWorker {
 }
 
 impl Worker<AudioControl, AudioStatus> for AudioWorker {
-    fn start(_c: Config) -> Self {
+    fn start(c: Config) -> Self {
         let (thread_tx, thread_rx) = mpsc::channel();
         let (worker_tx, worker_rx) = mpsc::channel();
         let handler = thread::Builder::new()
@@ -44,6 +44,7 @@ impl Worker<AudioControl, AudioStatus> for AudioWorker {
                 loop {
                     if let Ok(e) = thread_rx.recv() {
                         match e {
+                            AudioControl::Play(_) | AudioControl::Speak(_) if !c.sound => {}
                             AudioControl::Play(Sound::Error) => {
                                 let sound =
                                     SineWave::new(250).take_duration(Duration::from_millis(5));
@@ -90,4 +91,7 @@ impl Worker<AudioControl, AudioStatus> for AudioWorker {
     fn shutdown(&self) {
         self.send(AudioControl::Shutdown);
     }
+    fn join(self) {
+        let _ = self.handler.join();
+    }
 }
@@ -38,11 +38,9 @@ impl Worker<InputControl, InputStatus> for InputWorker {
                         }
                     }
 
-                    for key in stdin.keys() {
-                        if let Ok(key) = key {
-                            if let Err(_err) = thread_tx.send(InputStatus::Input(key)) {
-                                continue;
-                            }
+                    for key in stdin.keys().flatten() {
+                        if let Err(_err) = thread_tx.send(InputStatus::Input(key)) {
+                            continue;
                         }
                     }
                 })
@@ -59,4 +57,7 @@ impl Worker<InputControl, InputStatus> for InputWorker {
     fn shutdown(&self) {
         self.send(InputControl::Shutdown);
     }
+    fn join(self) {
+        let _ = self.handler.join();
+    }
 }
@@ -0,0 +1,607 @@
+use evdev::Key;
+use lazy_static::lazy_static;
+use log::warn;
+use regex::Regex;
+use std::collections::HashMap;
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+const XKB_ROOT: &str = "/usr/share/X11/xkb";
+const COMPOSE: &str = "/usr/share/X11/locale/en_US.UTF-8/Compose";
+/// How deep symbols files may include each other.
+const INCLUDE_LIMIT: usize = 16;
+/// Keysym names of 0x20 to 0x7e, which are their ASCII characters.
+const ASCII: [&str; 95] = [
+    "space", "exclam", "quotedbl", "numbersign", "dollar", "percent", "ampersand", "apostrophe",
+    "parenleft", "parenright", "asterisk", "plus", "comma", "minus", "period", "slash",
+    "0", "1", "2", "3", "4", "5", "6", "7",
+    "8", "9", "colon", "semicolon", "less", "equal", "greater", "question",
+    "at", "A", "B", "C", "D", "E", "F", "G",
+    "H", "I", "J", "K", "L", "M", "N", "O",
+    "P", "Q", "R", "S", "T", "U", "V", "W",
+    "X", "Y", "Z", "bracketleft", "backslash", "bracketright", "asciicircum", "underscore",
+    "grave", "a", "b", "c", "d", "e", "f", "g",
+    "h", "i", "j", "k", "l", "m", "n", "o",
+    "p", "q", "r", "s", "t", "u", "v", "w",
+    "x", "y", "z", "braceleft", "bar", "braceright", "asciitilde",
+];
+/// Keysym names of 0xa0 to 0xff, which are their Latin-1 characters.
+const LATIN1: [&str; 96] = [
+    "nobreakspace", "exclamdown", "cent", "sterling", "currency", "yen",
+    "brokenbar", "section", "diaeresis", "copyright", "ordfeminine", "guillemotleft",
+    "notsign", "hyphen", "registered", "macron", "degree", "plusminus",
+    "twosuperior", "threesuperior", "acute", "mu", "paragraph", "periodcentered",
+    "cedilla", "onesuperior", "masculine", "guillemotright", "onequarter", "onehalf",
+    "threequarters", "questiondown", "Agrave", "Aacute", "Acircumflex", "Atilde",
+    "Adiaeresis", "Aring", "AE", "Ccedilla", "Egrave", "Eacute",
+    "Ecircumflex", "Ediaeresis", "Igrave", "Iacute", "Icircumflex", "Idiaeresis",
+    "ETH", "Ntilde", "Ograve", "Oacute", "Ocircumflex", "Otilde",
+    "Odiaeresis", "multiply", "Oslash", "Ugrave", "Uacute", "Ucircumflex",
+    "Udiaeresis", "Yacute", "THORN", "ssharp", "agrave", "aacute",
+    "acircumflex", "atilde", "adiaeresis", "aring", "ae", "ccedilla",
+    "egrave", "eacute", "ecircumflex", "ediaeresis", "igrave", "iacute",
+    "icircumflex", "idiaeresis", "eth", "ntilde", "ograve", "oacute",
+    "ocircumflex", "otilde", "odiaeresis", "division", "oslash", "ugrave",
+    "uacute", "ucircumflex", "udiaeresis", "yacute", "thorn", "ydiaeresis",
+];
+
+/// The keys of the US layout and what they type without and with shift,
+/// for when the xkeyboard-config data isn't installed.
+const US: [(Key, char, char); 47] = [
+    (Key::KEY_GRAVE, '`', '~'), (Key::KEY_1, '1', '!'), (Key::KEY_2, '2', '@'),
+    (Key::KEY_3, '3', '#'), (Key::KEY_4, '4', '$'), (Key::KEY_5, '5', '%'),
+    (Key::KEY_6, '6', '^'), (Key::KEY_7, '7', '&'), (Key::KEY_8, '8', '*'),
+    (Key::KEY_9, '9', '('), (Key::KEY_0, '0', ')'), (Key::KEY_MINUS, '-', '_'),
+    (Key::KEY_EQUAL, '=', '+'), (Key::KEY_Q, 'q', 'Q'), (Key::KEY_W, 'w', 'W'),
+    (Key::KEY_E, 'e', 'E'), (Key::KEY_R, 'r', 'R'), (Key::KEY_T, 't', 'T'),
+    (Key::KEY_Y, 'y', 'Y'), (Key::KEY_U, 'u', 'U'), (Key::KEY_I, 'i', 'I'),
+    (Key::KEY_O, 'o', 'O'), (Key::KEY_P, 'p', 'P'), (Key::KEY_LEFTBRACE, '[', '{'),
+    (Key::KEY_RIGHTBRACE, ']', '}'), (Key::KEY_BACKSLASH, '\\', '|'), (Key::KEY_A, 'a', 'A'),
+    (Key::KEY_S, 's', 'S'), (Key::KEY_D, 'd', 'D'), (Key::KEY_F, 'f', 'F'),
+    (Key::KEY_G, 'g', 'G'), (Key::KEY_H, 'h', 'H'), (Key::KEY_J, 'j', 'J'),
+    (Key::KEY_K, 'k', 'K'), (Key::KEY_L, 'l', 'L'), (Key::KEY_SEMICOLON, ';', ':'),
+    (Key::KEY_APOSTROPHE, '\'', '"'), (Key::KEY_Z, 'z', 'Z'), (Key::KEY_X, 'x', 'X'),
+    (Key::KEY_C, 'c', 'C'), (Key::KEY_V, 'v', 'V'), (Key::KEY_B, 'b', 'B'),
+    (Key::KEY_N, 'n', 'N'), (Key::KEY_M, 'm', 'M'), (Key::KEY_COMMA, ',', '<'),
+    (Key::KEY_DOT, '.', '>'), (Key::KEY_SLASH, '/', '?'),
+];
+
+lazy_static! {
+    static ref COMMENT: Regex = Regex::new(r"(//|#)[^\n]*").unwrap();
+    static ref SECTION: Regex =
+        Regex::new(r#"((?:\w+\s+)*)xkb_symbols\s+"([^"]*)"\s*\{"#).unwrap();
+    static ref STATEMENT: Regex = Regex::new(
+        r#"\b(include|augment|override|replace)\s+"([^"]*)"|(?:\b(augment|override|replace)\s+)?key\s*<([^>]+)>\s*\{([^}]*)\}"#
+    )
+    .unwrap();
+    static ref GROUP1: Regex = Regex::new(r"symbols\[\s*Group1\s*\]\s*=\s*\[([^\]]*)\]").unwrap();
+    static ref ATTRIBUTE: Regex =
+        Regex::new(r#"\w+\[\s*\w+\s*\]\s*=\s*("[^"]*"|\[[^\]]*\])|\w+\s*=\s*"[^"]*""#).unwrap();
+    static ref LEVELS: Regex = Regex::new(r"\[([^\]]*)\]").unwrap();
+    static ref KEYCODE: Regex = Regex::new(r"<(\w+)>\s*=\s*(\d+)\s*;").unwrap();
+    static ref ALIAS: Regex = Regex::new(r"alias\s*<(\w+)>\s*=\s*<(\w+)>").unwrap();
+    static ref SEQUENCE: Regex =
+        Regex::new(r#"^((?:<\w+>\s*)+):\s*"((?:[^"\\]|\\.)*)"\s*(\w+)?"#).unwrap();
+}
+
+/// One key press with the modifiers held for it.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Stroke {
+    pub modifiers: Vec<Key>,
+    pub key: Key,
+}
+
+/// Which keys type which characters on the host keyboard layout.
+#[derive(Clone, Debug, Default)]
+pub struct Keymap {
+    chars: HashMap<char, Vec<Stroke>>,
+    /// Lowercase names of the keysyms on the first two levels.
+    keysyms: HashMap<String, Key>,
+}
+impl Keymap {
+    /// Build the keymap of an XKB layout such as `us`, `de` or `us(dvorak)`
+    /// from the xkeyboard-config data files, `$XKB_CONFIG_ROOT` or
+    /// `/usr/share/X11/xkb`. Characters behind a dead key are typed as the
+    /// dead key and its base, AltGr levels only when the layout sets right
+    /// alt to switch levels.
+    pub fn new(layout: &str) -> Result<Self, String> {
+        let root = env::var("XKB_CONFIG_ROOT").map_or_else(|_| PathBuf::from(XKB_ROOT), PathBuf::from);
+        Self::load(&root, Path::new(COMPOSE), layout)
+    }
+
+    /// Build the keymap of `layout` from the XKB data under `root` and the
+    /// compose table at `compose`.
+    fn load(root: &Path, compose: &Path, layout: &str) -> Result<Self, String> {
+        let (keycodes, aliases) = keycodes(root)?;
+        let mut symbols = Symbols {
+            aliases,
+            ..Symbols::default()
+        };
+        symbols.include(root, layout, Merge::Override, 0)?;
+        if symbols.keys.is_empty() {
+            return Err(format!("no keys in layout {}", layout));
+        }
+        let compose = Compose::read(compose);
+        let level3 = symbols
+            .keys
+            .get("RALT")
+            .is_some_and(|levels| levels.first().map(String::as_str) == Some("ISO_Level3_Shift"));
+        let modifiers = [
+            vec![],
+            vec![Key::KEY_LEFTSHIFT],
+            vec![Key::KEY_RIGHTALT],
+            vec![Key::KEY_LEFTSHIFT, Key::KEY_RIGHTALT],
+        ];
+        // lower keycodes first so the table is the same every run
+        let mut keys: Vec<(Key, &Vec<String>)> = symbols
+            .keys
+            .iter()
+            .filter_map(|(name, levels)| keycodes.get(name).map(|&key| (key, levels)))
+            .collect();
+        keys.sort_by_key(|(key, _)| key.code());
+
+        let mut chars: HashMap<char, Vec<Stroke>> = HashMap::new();
+        let mut dead: HashMap<&str, Stroke> = HashMap::new();
+        let mut keysyms: HashMap<String, Key> = HashMap::new();
+        for (level, modifiers) in modifiers.iter().enumerate().take(if level3 { 4 } else { 2 }) {
+            for (key, levels) in keys.iter() {
+                let keysym = match levels.get(level) {
+                    Some(keysym) => keysym.as_str(),
+                    None => continue,
+                };
+                if level < 2 {
+                    keysyms.entry(keysym.to_lowercase()).or_insert(*key);
+                }
+                let stroke = Stroke {
+                    modifiers: modifiers.clone(),
+                    key: *key,
+                };
+                if keysym.starts_with("dead_") {
+                    dead.entry(keysym).or_insert(stroke);
+                } else if let Some(c) = compose.char(keysym) {
+                    chars.entry(c).or_insert_with(|| vec![stroke]);
+                }
+            }
+        }
+        for (accent, base, c) in compose.dead.iter() {
+            if chars.contains_key(c) {
+                continue;
+            }
+            let accent = match dead.get(accent.as_str()) {
+                Some(stroke) => stroke.clone(),
+                None => continue,
+            };
+            let base = match dead.get(base.as_str()) {
+                Some(stroke) => Some(stroke.clone()),
+                None => compose
+                    .char(base)
+                    .and_then(|b| chars.get(&b))
+                    .filter(|strokes| strokes.len() == 1)
+                    .map(|strokes| strokes[0].clone()),
+            };
+            if let Some(base) = base {
+                chars.insert(*c, vec![accent, base]);
+            }
+        }
+        Self::pc_keys(&mut chars);
+        Ok(Self { chars, keysyms })
+    }
+
+    /// The US layout without reading any XKB data, the fallback when it
+    /// can't be found.
+    pub fn us() -> Self {
+        let mut chars: HashMap<char, Vec<Stroke>> = HashMap::new();
+        let mut keysyms: HashMap<String, Key> = HashMap::new();
+        for &(key, lower, upper) in US.iter() {
+            for (modifiers, c) in [(vec![], lower), (vec![Key::KEY_LEFTSHIFT], upper)] {
+                keysyms.entry(ASCII[c as usize - 0x20].to_lowercase()).or_insert(key);
+                chars.insert(c, vec![Stroke { modifiers, key }]);
+            }
+        }
+        keysyms.insert("space".to_string(), Key::KEY_SPACE);
+        Self::pc_keys(&mut chars);
+        Self { chars, keysyms }
+    }
+
+    /// Keys layouts leave to the pc symbols.
+    fn pc_keys(chars: &mut HashMap<char, Vec<Stroke>>) {
+        for (c, key) in [(' ', Key::KEY_SPACE), ('\n', Key::KEY_ENTER), ('\t', Key::KEY_TAB)] {
+            chars.entry(c).or_insert_with(|| {
+                vec![Stroke {
+                    modifiers: Vec::new(),
+                    key,
+                }]
+            });
+        }
+    }
+
+    pub fn get(&self, c: char) -> Option<&[Stroke]> {
+        self.chars.get(&c).map(Vec::as_slice)
+    }
+
+    /// The key of a keysym on the layout, its name in any case.
+    pub fn keysym(&self, name: &str) -> Option<Key> {
+        self.keysyms.get(&name.to_lowercase()).copied()
+    }
+
+    /// Every key and modifier the keymap presses.
+    pub fn keys(&self) -> impl Iterator<Item = Key> + '_ {
+        self.chars
+            .values()
+            .flatten()
+            .flat_map(|stroke| stroke.modifiers.iter().copied().chain(Some(stroke.key)))
+            .chain(self.keysyms.values().copied())
+    }
+}
+
+/// Key name aliases such as `<LatQ>` to the names they stand for.
+type Aliases = HashMap<String, String>;
+
+/// `<AE01>` style key names to evdev keys, X keycodes being evdev's plus 8,
+/// and the aliases of the names.
+fn keycodes(root: &Path) -> Result<(HashMap<String, Key>, Aliases), String> {
+    let path = root.join("keycodes").join("evdev");
+    let contents = fs::read_to_string(&path).map_err(|e| format!("{}: {}", path.display(), e))?;
+    let contents = COMMENT.replace_all(&contents, "");
+    let keycodes: HashMap<String, Key> = KEYCODE
+        .captures_iter(&contents)
+        .filter_map(|c| {
+            let code: u16 = c[2].parse().ok()?;
+            Some((c[1].to_string(), Key::new(code.checked_sub(8)?)))
+        })
+        .collect();
+    let aliases = ALIAS
+        .captures_iter(&contents)
+        .map(|c| (c[1].to_string(), c[2].to_string()))
+        .collect();
+    Ok((keycodes, aliases))
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum Merge {
+    Override,
+    Augment,
+    Replace,
+}
+
+/// The keysyms of each key by level, merged over a layout's includes.
+#[derive(Default)]
+struct Symbols {
+    keys: HashMap<String, Vec<String>>,
+    /// So `<LatQ>` and `<AD01>` merge as one key.
+    aliases: Aliases,
+}
+impl Symbols {
+    /// Load `file(section)`, or several joined by `+` and `|`.
+    fn include(&mut self, root: &Path, name: &str, merge: Merge, depth: usize) -> Result<(), String> {
+        if depth > INCLUDE_LIMIT {
+            return Err(format!("{} includes too deeply", name));
+        }
+        for part in name.split(['+', '|']).filter(|p| !p.is_empty()) {
+            let (file, section) = match part.find('(') {
+                Some(i) => (&part[..i], Some(part[i + 1..].trim_end_matches(')'))),
+                None => (part, None),
+            };
+            // `:2` style group suffixes only matter for multi group layouts
+            let file = file.split(':').next().unwrap_or(file);
+            let path = root.join("symbols").join(file);
+            let contents = fs::read_to_string(&path).map_err(|e| format!("{}: {}", path.display(), e))?;
+            let contents = COMMENT.replace_all(&contents, "");
+            let body = Self::section(&contents, section)
+                .ok_or_else(|| format!("{}: no section {}", path.display(), section.unwrap_or("default")))?;
+            for c in STATEMENT.captures_iter(body) {
+                if let (Some(mode), Some(included)) = (c.get(1), c.get(2)) {
+                    let mode = match mode.as_str() {
+                        "augment" => Merge::Augment,
+                        _ => merge,
+                    };
+                    self.include(root, included.as_str(), mode, depth + 1)?;
+                } else if let (Some(key), Some(definition)) = (c.get(4), c.get(5)) {
+                    let mode = match c.get(3).map(|m| m.as_str()) {
+                        Some("augment") => Merge::Augment,
+                        Some("override") => Merge::Override,
+                        Some("replace") => Merge::Replace,
+                        _ => merge,
+                    };
+                    if let Some(levels) = Self::levels(definition.as_str()) {
+                        self.merge(key.as_str(), levels, mode);
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// The body of the named section, or the `default` one, else the first.
+    fn section<'a>(contents: &'a str, name: Option<&str>) -> Option<&'a str> {
+        let mut first = None;
+        for c in SECTION.captures_iter(contents) {
+            let start = c.get(0).unwrap().end();
+            let mut depth = 1;
+            let end = contents[start..].char_indices().find_map(|(i, ch)| {
+                match ch {
+                    '{' => depth += 1,
+                    '}' => depth -= 1,
+                    _ => {}
+                }
+                if depth == 0 {
+                    Some(start + i)
+                } else {
+                    None
+                }
+            })?;
+            let body = &contents[start..end];
+            let found = match name {
+                Some(name) => &c[2] == name,
+                None => c[1].split_whitespace().any(|flag| flag == "default"),
+            };
+            if found {
+                return Some(body);
+            }
+            first = first.or(Some(body));
+        }
+        if name.is_none() {
+            first
+        } else {
+            None
+        }
+    }
+
+    /// The Group1 keysyms of a key definition.
+    fn levels(definition: &str) -> Option<Vec<String>> {
+        let list = match GROUP1.captures(definition) {
+            Some(c) => c[1].to_string(),
+            None => {
+                let stripped = ATTRIBUTE.replace_all(definition, "");
+                LEVELS.captures(&stripped)?[1].to_string()
+            }
+        };
+        Some(list.split(',').map(|keysym| keysym.trim().to_string()).collect())
+    }
+
+    fn merge(&mut self, key: &str, levels: Vec<String>, merge: Merge) {
+        let key = self.aliases.get(key).map_or(key, String::as_str);
+        let old = self.keys.entry(key.to_string()).or_default();
+        if merge == Merge::Replace {
+            old.clear();
+        }
+        for (level, keysym) in levels.into_iter().enumerate() {
+            if keysym.is_empty() || keysym == "NoSymbol" {
+                continue;
+            }
+            if old.len() <= level {
+                old.resize(level + 1, String::new());
+            }
+            if merge != Merge::Augment || old[level].is_empty() {
+                old[level] = keysym;
+            }
+        }
+    }
+}
+
+/// What the compose table knows: characters of keysym names beyond Latin-1
+/// and the characters a dead key makes of the keysym after it.
+#[derive(Default)]
+struct Compose {
+    names: HashMap<String, char>,
+    dead: Vec<(String, String, char)>,
+}
+impl Compose {
+    fn read(path: &Path) -> Self {
+        let mut compose = Self::default();
+        let contents = match fs::read_to_string(path) {
+            Ok(contents) => contents,
+            Err(e) => {
+                warn!("{}: {}", path.display(), e);
+                return compose;
+            }
+        };
+        for line in contents.lines() {
+            let c = match SEQUENCE.captures(line) {
+                Some(c) => c,
+                None => continue,
+            };
+            let mut result = c[2].chars().filter(|&ch| ch != '\\');
+            let ch = match (result.next(), result.next()) {
+                (Some(ch), None) => ch,
+                _ => continue,
+            };
+            if let Some(name) = c.get(3) {
+                compose.names.entry(name.as_str().to_string()).or_insert(ch);
+            }
+            let keysyms: Vec<&str> = c[1]
+                .split(|c: char| c == '<' || c == '>' || c.is_whitespace())
+                .filter(|k| !k.is_empty())
+                .collect();
+            if let [accent, base] = keysyms[..] {
+                if accent.starts_with("dead_") {
+                    compose.dead.push((accent.to_string(), base.to_string(), ch));
+                }
+            }
+        }
+        compose
+    }
+
+    /// The character a keysym types.
+    fn char(&self, keysym: &str) -> Option<char> {
+        if let Some(i) = ASCII.iter().position(|&name| name == keysym) {
+            return char::from_u32(0x20 + i as u32);
+        }
+        if let Some(i) = LATIN1.iter().position(|&name| name == keysym) {
+            return char::from_u32(0xa0 + i as u32);
+        }
+        if let Some(hex) = keysym.strip_prefix("0x") {
+            return match u32::from_str_radix(hex, 16).ok()? {
+                code if code >= 0x0100_0000 => char::from_u32(code - 0x0100_0000),
+                code if (0x20..0x100).contains(&code) => char::from_u32(code),
+                _ => None,
+            };
+        }
+        if let Some(hex) = keysym.strip_prefix('U').filter(|hex| hex.len() >= 4) {
+            if let Ok(code) = u32::from_str_radix(hex, 16) {
+                return char::from_u32(code);
+            }
+        }
+        self.names.get(keysym).copied()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const KEYCODES: &str = r#"
+default xkb_keycodes "evdev" {
+    <AE01> = 10;
+    <AD01> = 24;
+    <AD03> = 26;
+    <AC01> = 38;
+    <AC11> = 48;
+    <AB01> = 52;
+    <RALT> = 108;
+    alias <LatQ> = <AD01>;
+    alias <LatA> = <AC01>;
+};
+"#;
+    const LATIN: &str = r#"
+// levels 3 and 4 only reachable with a level 3 switch
+default partial alphanumeric_keys
+xkb_symbols "basic" {
+    key <AE01> { [ 1, exclam ] };
+    key <AD01> { [ q, Q ] };
+    key <AD03> { [ e, E, EuroSign, cent ] };
+    key <AC01> { [ a, A ] };
+    key <AB01> { [ z, Z ] };
+};
+"#;
+    const LEVEL3: &str = r#"
+partial modifier_keys
+xkb_symbols "ralt_switch" {
+    key <RALT> { type[Group1] = "ONE_LEVEL", symbols[Group1] = [ ISO_Level3_Shift ] };
+};
+"#;
+    const FR: &str = r#"
+default partial alphanumeric_keys
+xkb_symbols "basic" {
+    include "latin"
+    name[Group1] = "French";
+    key <AD01> { [ a, A ] };
+    key <LatA> { [ q, Q ] };
+    key <AB01> { [ w, W ] };
+    key <AC11> { [ dead_circumflex, dead_diaeresis ] };
+    include "level3(ralt_switch)"
+};
+"#;
+    const COMPOSE: &str = r#"
+<dead_circumflex> <e> : "ê" ecircumflex # LATIN SMALL LETTER E WITH CIRCUMFLEX
+<dead_diaeresis> <e> : "ë" ediaeresis
+<Multi_key> <C> <equal> : "€" EuroSign
+"#;
+
+    /// XKB data files under a directory of their own.
+    struct Fixture(PathBuf);
+    impl Fixture {
+        fn new(name: &str) -> Self {
+            let root = env::temp_dir().join(format!("wayplover-xkb-{}-{}", name, std::process::id()));
+            let files = [
+                ("keycodes/evdev", KEYCODES),
+                ("symbols/latin", LATIN),
+                ("symbols/level3", LEVEL3),
+                ("symbols/fr", FR),
+                ("Compose", COMPOSE),
+            ];
+            for (path, contents) in files.iter() {
+                let path = root.join(path);
+                fs::create_dir_all(path.parent().unwrap()).unwrap();
+                fs::write(path, contents).unwrap();
+            }
+            Self(root)
+        }
+
+        fn keymap(&self, layout: &str) -> Keymap {
+            Keymap::load(&self.0, &self.0.join("Compose"), layout).unwrap()
+        }
+    }
+    impl Drop for Fixture {
+        fn drop(&mut self) {
+            let _ = fs::remove_dir_all(&self.0);
+        }
+    }
+
+    fn stroke(modifiers: &[Key], key: Key) -> Stroke {
+        Stroke {
+            modifiers: modifiers.to_vec(),
+            key,
+        }
+    }
+
+    #[test]
+    fn layout_with_includes() {
+        let fixture = Fixture::new("includes");
+        let fr = fixture.keymap("fr");
+        assert_eq!(fr.get('a'), Some(&[stroke(&[], Key::KEY_Q)][..]));
+        assert_eq!(fr.get('Q'), Some(&[stroke(&[Key::KEY_LEFTSHIFT], Key::KEY_A)][..]));
+        assert_eq!(fr.get('w'), Some(&[stroke(&[], Key::KEY_Z)][..]));
+        // included from latin
+        assert_eq!(fr.get('!'), Some(&[stroke(&[Key::KEY_LEFTSHIFT], Key::KEY_1)][..]));
+        // overridden by fr, once through the <LatA> alias
+        assert_eq!(fr.get('z'), None);
+        assert_eq!(fr.get('q'), Some(&[stroke(&[], Key::KEY_A)][..]));
+        assert_eq!(fr.keysym("A"), Some(Key::KEY_Q));
+        assert_eq!(fr.keysym("q"), Some(Key::KEY_A));
+        assert!(Keymap::load(&fixture.0, &fixture.0.join("Compose"), "fr(azerty)").is_err());
+    }
+
+    #[test]
+    fn altgr_levels() {
+        let fixture = Fixture::new("altgr");
+        let fr = fixture.keymap("fr");
+        assert_eq!(fr.get('€'), Some(&[stroke(&[Key::KEY_RIGHTALT], Key::KEY_E)][..]));
+        assert_eq!(
+            fr.get('¢'),
+            Some(&[stroke(&[Key::KEY_LEFTSHIFT, Key::KEY_RIGHTALT], Key::KEY_E)][..])
+        );
+        // without a level 3 switch
+        let latin = fixture.keymap("latin");
+        assert_eq!(latin.get('e'), Some(&[stroke(&[], Key::KEY_E)][..]));
+        assert_eq!(latin.get('€'), None);
+    }
+
+    #[test]
+    fn dead_keys_through_compose() {
+        let fixture = Fixture::new("dead");
+        let fr = fixture.keymap("fr");
+        assert_eq!(
+            fr.get('ê'),
+            Some(&[stroke(&[], Key::KEY_APOSTROPHE), stroke(&[], Key::KEY_E)][..])
+        );
+        assert_eq!(
+            fr.get('ë'),
+            Some(&[stroke(&[Key::KEY_LEFTSHIFT], Key::KEY_APOSTROPHE), stroke(&[], Key::KEY_E)][..])
+        );
+        assert_eq!(fr.get('^'), None);
+    }
+
+    #[test]
+    fn builtin_us_layout() {
+        let us = Keymap::us();
+        let stroke = |modifiers: Vec<Key>, key| vec![Stroke { modifiers, key }];
+        assert_eq!(us.get('a'), Some(&stroke(vec![], Key::KEY_A)[..]));
+        assert_eq!(us.get('?'), Some(&stroke(vec![Key::KEY_LEFTSHIFT], Key::KEY_SLASH)[..]));
+        assert_eq!(us.get('\n'), Some(&stroke(vec![], Key::KEY_ENTER)[..]));
+        assert_eq!(us.keysym("C"), Some(Key::KEY_C));
+        assert_eq!(us.keysym("bracketleft"), Some(Key::KEY_LEFTBRACE));
+        assert_eq!(us.get('é'), None);
+    }
+
+    #[test]
+    #[ignore = "needs the xkeyboard-config data installed"]
+    fn builtin_us_layout_matches_xkb() {
+        let xkb = Keymap::new("us").unwrap();
+        let us = Keymap::us();
+        for c in (0x20..0x7f).map(char::from) {
+            assert_eq!(us.get(c), xkb.get(c), "{:?}", c);
+        }
+    }
+}